@@ -0,0 +1,38 @@
+// Measures how long crsh takes to run a trivial command, compared to spawning and
+// waiting on it directly. The difference is the per-command overhead of the shell.
+// Run with `cargo bench`.
+use crsh::parser::Parser;
+use crsh::scanner::Scanner;
+use crsh::Crsh;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 500;
+
+fn per_command(total: Duration) -> Duration {
+    total / ITERATIONS
+}
+
+fn main() {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        Command::new("true").status().expect("Failed running true");
+    }
+    let direct = per_command(start.elapsed());
+
+    let mut interpreter = Crsh::new();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let tokens = Scanner::new("true".to_string()).scan_tokens().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        interpreter.execute(ast).expect("Failed running true");
+    }
+    let shell = per_command(start.elapsed());
+
+    println!("spawning `true` directly: {:?} per command", direct);
+    println!("running `true` in crsh:   {:?} per command", shell);
+    println!(
+        "crsh overhead:            {:?} per command",
+        shell.saturating_sub(direct)
+    );
+}
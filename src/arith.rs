@@ -0,0 +1,499 @@
+// shell arithmetic as in "for ((i = 0; i < n; i++))", on 64-bit integers. Results that don't
+// fit are an error.
+
+// the variables an expression reads and assigns
+pub trait ArithEnv {
+    fn variable(&self, name: &str) -> Option<String>;
+    fn set_variable(&mut self, name: &str, value: i64);
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ArithError(pub String);
+
+// how deep expressions may nest, in parentheses, after operators like "-" or "**", or in
+// variables that refer to other expressions as in "a=b; b=a; echo $((a))". Kept low, as
+// each level takes a few frames of the parser and evaluator.
+const MAX_RECURSION: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+enum ArithToken {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+// longer operators first, so "<<=" isn't scanned as "<" followed by "<="
+const OPERATORS: [&str; 37] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",",
+];
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    // left associative operators of the same precedence, as in "a - b + c", kept flat so
+    // that long chains don't nest
+    Chain(Box<Expr>, Vec<(&'static str, Expr)>),
+    // "=" or an operator assignment like "+="
+    Assign(&'static str, String, Box<Expr>),
+    // the variable, the change and whether the old value is the result, as for "i++"
+    Increment(String, i64, bool),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+// evaluates an expression, an empty one is 0
+pub fn evaluate(expression: &str, env: &mut impl ArithEnv) -> Result<i64, ArithError> {
+    evaluate_nested(expression, env, 0)
+}
+
+fn evaluate_nested(
+    expression: &str,
+    env: &mut impl ArithEnv,
+    depth: usize,
+) -> Result<i64, ArithError> {
+    if depth > MAX_RECURSION {
+        return Err(ArithError("expression recursion level exceeded".into()));
+    }
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = ArithParser {
+        tokens,
+        curr: 0,
+        depth,
+        deepest: depth,
+    };
+    let expr = parser.comma()?;
+    if let Some(token) = parser.tokens.get(parser.curr) {
+        return Err(syntax_error(token));
+    }
+    // variables are evaluated below the deepest level of this expression
+    let depth = parser.deepest;
+    Evaluator { env, depth }.eval(&expr)
+}
+
+fn syntax_error(token: &ArithToken) -> ArithError {
+    let token = match token {
+        ArithToken::Number(number) => number.to_string(),
+        ArithToken::Name(name) => name.clone(),
+        ArithToken::Operator(operator) => operator.to_string(),
+    };
+    ArithError(format!(
+        "syntax error in expression (error token is \"{}\")",
+        token
+    ))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<ArithToken>, ArithError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut idx = 0;
+    while idx < chars.len() {
+        let chr = chars[idx];
+        if chr.is_whitespace() {
+            idx += 1;
+        } else if chr.is_ascii_alphanumeric() || chr == '_' || chr == '$' {
+            // "$x" and "${x}" are the same as "x", and "$1" or "$#" are parameters
+            let dollar = chr == '$';
+            let braced = dollar && chars.get(idx + 1) == Some(&'{');
+            idx += dollar as usize + braced as usize;
+            let start = idx;
+            if dollar && !braced && chars.get(idx).is_some_and(|chr| "?!#@*".contains(*chr)) {
+                idx += 1;
+            } else {
+                while chars.get(idx).is_some_and(|chr| {
+                    chr.is_ascii_alphanumeric() || "_@#".contains(*chr) || (braced && *chr != '}')
+                }) {
+                    idx += 1;
+                }
+            }
+            let word: String = chars[start..idx].iter().collect();
+            if braced {
+                if chars.get(idx) != Some(&'}') {
+                    return Err(ArithError("bad substitution".into()));
+                }
+                idx += 1;
+            }
+            if word.is_empty() {
+                return Err(ArithError("syntax error: operand expected".into()));
+            }
+            let token = match word.chars().next() {
+                Some(first) if first.is_ascii_digit() && !dollar => {
+                    ArithToken::Number(number(&word)?)
+                }
+                _ => ArithToken::Name(word),
+            };
+            tokens.push(token);
+        } else {
+            let rest: String = chars[idx..chars.len().min(idx + 3)].iter().collect();
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+                .copied();
+            match (chr, operator) {
+                ('(' | ')', _) => {
+                    tokens.push(ArithToken::Operator(if chr == '(' { "(" } else { ")" }))
+                }
+                (_, Some(operator)) => {
+                    idx += operator.len() - 1;
+                    tokens.push(ArithToken::Operator(operator));
+                }
+                _ => {
+                    return Err(ArithError(format!(
+                        "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        chr
+                    )))
+                }
+            }
+            idx += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+// parses "42", "0x2a", "052" or "base#digits" like "2#101010"
+fn number(word: &str) -> Result<i64, ArithError> {
+    let invalid = || ArithError(format!("{}: value too great for base", word));
+    let (base, digits) = if let Some((base, digits)) = word.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base @ 2..=64) => (base, digits),
+            _ => return Err(ArithError(format!("{}: invalid arithmetic base", word))),
+        }
+    } else if let Some(digits) = word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+        (16, digits)
+    } else if word.len() > 1 && word.starts_with('0') {
+        (8, &word[1..])
+    } else {
+        (10, word)
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    let mut value: i64 = 0;
+    for chr in digits.chars() {
+        // bases up to 36 don't care about case, larger ones continue with A-Z, "@" and "_"
+        let digit = match chr {
+            '0'..='9' => chr as u32 - '0' as u32,
+            'a'..='z' => chr as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => chr as u32 - 'A' as u32 + 10,
+            'A'..='Z' => chr as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value
+            .checked_mul(base as i64)
+            .and_then(|value| value.checked_add(digit as i64))
+            .ok_or_else(overflow)?;
+    }
+    Ok(value)
+}
+
+// binary operators from the lowest to the highest precedence, all left associative
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const ASSIGNMENTS: [&str; 11] = [
+    "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+struct ArithParser {
+    tokens: Vec<ArithToken>,
+    curr: usize,
+    // how deep the expression nests at the current token, and at most
+    depth: usize,
+    deepest: usize,
+}
+
+impl ArithParser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.curr) {
+            Some(ArithToken::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn match_operator(&mut self, operator: &str) -> bool {
+        let matched = self.peek_operator() == Some(operator);
+        if matched {
+            self.curr += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), ArithError> {
+        if self.match_operator(operator) {
+            return Ok(());
+        }
+        Err(match self.tokens.get(self.curr) {
+            Some(token) => syntax_error(token),
+            None => ArithError(format!("syntax error: \"{}\" expected", operator)),
+        })
+    }
+
+    // parses a part of the expression that nests inside the current one
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, ArithError>,
+    ) -> Result<Expr, ArithError> {
+        if self.depth >= MAX_RECURSION {
+            return Err(ArithError("expression recursion level exceeded".into()));
+        }
+        self.depth += 1;
+        self.deepest = self.deepest.max(self.depth);
+        let res = parse(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn comma(&mut self) -> Result<Expr, ArithError> {
+        let first = self.assignment()?;
+        let mut rest = vec![];
+        while self.match_operator(",") {
+            rest.push((",", self.assignment()?));
+        }
+        Ok(Self::chain(first, rest))
+    }
+
+    fn chain(first: Expr, rest: Vec<(&'static str, Expr)>) -> Expr {
+        match rest.is_empty() {
+            true => first,
+            false => Expr::Chain(Box::new(first), rest),
+        }
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ArithError> {
+        if let (Some(ArithToken::Name(name)), Some(ArithToken::Operator(operator))) =
+            (self.tokens.get(self.curr), self.tokens.get(self.curr + 1))
+        {
+            if ASSIGNMENTS.contains(operator) {
+                let (name, operator) = (name.clone(), *operator);
+                self.curr += 2;
+                let value = self.nested(Self::assignment)?;
+                return Ok(Expr::Assign(operator, name, Box::new(value)));
+            }
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, ArithError> {
+        let condition = self.binary(0)?;
+        if !self.match_operator("?") {
+            return Ok(condition);
+        }
+        let then = self.nested(Self::comma)?;
+        self.expect(":")?;
+        let otherwise = self.nested(Self::conditional)?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ArithError> {
+        if level == BINARY_LEVELS.len() {
+            return self.power();
+        }
+        let first = self.binary(level + 1)?;
+        let mut rest = vec![];
+        while let Some(operator) = self
+            .peek_operator()
+            .filter(|operator| BINARY_LEVELS[level].contains(operator))
+        {
+            self.curr += 1;
+            rest.push((operator, self.binary(level + 1)?));
+        }
+        Ok(Self::chain(first, rest))
+    }
+
+    // "**" is right associative and binds tighter than the other binary operators
+    fn power(&mut self) -> Result<Expr, ArithError> {
+        let base = self.unary()?;
+        if self.match_operator("**") {
+            let exponent = self.nested(Self::power)?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ArithError> {
+        match self.peek_operator() {
+            Some(operator @ ("++" | "--")) => {
+                self.curr += 1;
+                let Some(ArithToken::Name(name)) = self.tokens.get(self.curr).cloned() else {
+                    return Err(ArithError(format!(
+                        "syntax error: variable expected after \"{}\"",
+                        operator
+                    )));
+                };
+                self.curr += 1;
+                let change = if operator == "++" { 1 } else { -1 };
+                Ok(Expr::Increment(name, change, false))
+            }
+            Some(operator @ ("+" | "-" | "!" | "~")) => {
+                self.curr += 1;
+                Ok(Expr::Unary(operator, Box::new(self.nested(Self::unary)?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ArithError> {
+        match self.tokens.get(self.curr).cloned() {
+            Some(ArithToken::Number(number)) => {
+                self.curr += 1;
+                Ok(Expr::Number(number))
+            }
+            Some(ArithToken::Name(name)) => {
+                self.curr += 1;
+                match self.peek_operator() {
+                    Some(operator @ ("++" | "--")) => {
+                        self.curr += 1;
+                        let change = if operator == "++" { 1 } else { -1 };
+                        Ok(Expr::Increment(name, change, true))
+                    }
+                    _ => Ok(Expr::Variable(name)),
+                }
+            }
+            Some(ArithToken::Operator("(")) => {
+                self.curr += 1;
+                let expr = self.nested(Self::comma)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(syntax_error(&token)),
+            None => Err(ArithError("syntax error: operand expected".into())),
+        }
+    }
+}
+
+struct Evaluator<'a, E: ArithEnv> {
+    env: &'a mut E,
+    depth: usize,
+}
+
+impl<E: ArithEnv> Evaluator<'_, E> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ArithError> {
+        match expr {
+            Expr::Number(number) => Ok(*number),
+            Expr::Variable(name) => self.variable(name),
+            Expr::Unary(operator, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *operator {
+                    "-" => value.checked_neg().ok_or_else(overflow)?,
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Chain(first, rest) => {
+                let mut value = self.eval(first)?;
+                for (operator, operand) in rest {
+                    value = match *operator {
+                        // the right side of "&&" and "||" is only evaluated if it matters
+                        "&&" => (value != 0 && self.eval(operand)? != 0) as i64,
+                        "||" => (value != 0 || self.eval(operand)? != 0) as i64,
+                        operator => apply(operator, value, self.eval(operand)?)?,
+                    };
+                }
+                Ok(value)
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                apply(operator, left, right)
+            }
+            Expr::Assign(operator, name, value) => {
+                let value = self.eval(value)?;
+                let value = match operator.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    Some(operator) => apply(operator, self.variable(name)?, value)?,
+                    None => value,
+                };
+                self.env.set_variable(name, value);
+                Ok(value)
+            }
+            Expr::Increment(name, change, postfix) => {
+                let old = self.variable(name)?;
+                let new = old.checked_add(*change).ok_or_else(overflow)?;
+                self.env.set_variable(name, new);
+                Ok(if *postfix { old } else { new })
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.eval(condition)? != 0 {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+        }
+    }
+
+    // unset and empty variables are 0, others may hold expressions themselves
+    fn variable(&mut self, name: &str) -> Result<i64, ArithError> {
+        let value = self.env.variable(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(number) = value.parse::<i64>() {
+            return Ok(number);
+        }
+        evaluate_nested(value, self.env, self.depth + 1)
+    }
+}
+
+fn overflow() -> ArithError {
+    ArithError("integer overflow".into())
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, ArithError> {
+    let value = match operator {
+        "," => right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "<<" | ">>" if !(0..64).contains(&right) => {
+            return Err(ArithError(format!("shift count out of range ({})", right)))
+        }
+        "<<" => left << right,
+        ">>" => left >> right,
+        "+" => left.checked_add(right).ok_or_else(overflow)?,
+        "-" => left.checked_sub(right).ok_or_else(overflow)?,
+        "*" => left.checked_mul(right).ok_or_else(overflow)?,
+        "/" | "%" if right == 0 => return Err(ArithError("division by 0".into())),
+        "/" => left.checked_div(right).ok_or_else(overflow)?,
+        "%" => left.checked_rem(right).ok_or_else(overflow)?,
+        "**" if right < 0 => return Err(ArithError("exponent less than 0".into())),
+        "**" => u32::try_from(right)
+            .ok()
+            .and_then(|right| left.checked_pow(right))
+            .ok_or_else(overflow)?,
+        _ => return Err(ArithError(format!("unknown operator \"{}\"", operator))),
+    };
+    Ok(value)
+}
@@ -1,9 +1,369 @@
-#[derive(Debug, PartialEq)]
+use std::fmt::Display;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Node {
-    Command(Vec<String>, Vec<Node>),
+    Command(Vec<Node>, Vec<Node>),
     Pipeline(Vec<Node>),
-    RedirectAppend(String),
-    RedirectWrite(String),
-    RedirectRead(String),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Word(String),
+    ExpandableWord(Vec<WordPart>),
+    // the name, which may have a subscript as in "arr[1]=x", and the value
+    Assignment(String, Box<Node>),
+    // "name+=value" appends to a string, or to an array with "name+=(...)"
+    AppendAssignment(String, Box<Node>),
+    // the elements of "name=(a [5]=b)", with their subscripts if they have one
+    Array(Vec<(Option<String>, Node)>),
+    // the file descriptor that is redirected, and the target word
+    RedirectAppend(i32, Box<Node>),
+    RedirectWrite(i32, Box<Node>),
+    RedirectRead(i32, Box<Node>),
+    RedirectReadWrite(i32, Box<Node>),
+    // n>&m, or n>&- to close n
+    RedirectDuplicate(i32, Box<Node>),
+    // the delimiter and the body
+    RedirectHereDoc(i32, String, Box<Node>),
+    RedirectHereString(i32, Box<Node>),
     CommandSequence(Vec<Node>),
+    Background(Box<Node>),
+    // "( list )" runs in a forked copy of the shell, "{ list; }" in the shell itself.
+    // Both are followed by their redirects.
+    Subshell(Box<Node>, Vec<Node>),
+    Group(Box<Node>, Vec<Node>),
+    // the condition and body of "if" and each "elif", the "else" body and the redirects
+    If(Vec<(Node, Node)>, Option<Box<Node>>, Vec<Node>),
+    // the condition, the body and the redirects
+    While(Box<Node>, Box<Node>, Vec<Node>),
+    Until(Box<Node>, Box<Node>, Vec<Node>),
+    // the variable, the words after "in" or None for the positional parameters, the body and
+    // the redirects
+    For(String, Option<Vec<Node>>, Box<Node>, Vec<Node>),
+    // the initialization, condition and step of "for ((...))", the body and the redirects
+    ArithFor(String, String, String, Box<Node>, Vec<Node>),
+    // the word, the items and the redirects
+    Case(Box<Node>, Vec<CaseItem>, Vec<Node>),
+    // like For, but the words are a menu to pick from
+    Select(String, Option<Vec<Node>>, Box<Node>, Vec<Node>),
+    // "name() compound-command", the body keeps its redirects
+    FunctionDef(String, Box<Node>),
+    // the expression of "((...))" and the redirects
+    Arithmetic(String, Vec<Node>),
+}
+
+// "pattern | pattern) body" followed by ";;", ";&" or ";;&"
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Node>,
+    pub body: Node,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaseTerminator {
+    // ";;" ends the case statement
+    Break,
+    // ";&" runs the next body as well
+    FallThrough,
+    // ";;&" goes on matching the next patterns
+    Continue,
+}
+
+impl Display for CaseTerminator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Break => write!(f, ";;"),
+            Self::FallThrough => write!(f, ";&"),
+            Self::Continue => write!(f, ";;&"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WordPart {
+    Literal(String),
+    Variable(String),
+    DoubleQuoted(Vec<WordPart>),
+    // $(...) or `...`
+    CommandSubstitution(Box<Node>),
+    // unquoted "*", "?" or a bracket expression like "[a-z]"
+    Glob(String),
+    // "~" followed by a user name, "+", "-" or nothing
+    Tilde(String),
+    // the alternatives of "{a,b}", a sequence like "{1..3}" is scanned into its words
+    Brace(Vec<Vec<WordPart>>),
+    // the expression of "$((...))", expansions in it are scanned when it's evaluated
+    Arithmetic(String),
+    // "${name<operator>...}" like "${name:-default}" or "${name%.*}"
+    Parameter(String, ParameterOperator),
+}
+
+// what "${name...}" does with the value of the parameter. The words after an operator can
+// contain expansions, and are patterns for the operators that remove or replace text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterOperator {
+    // "-" uses the word if the parameter is unset, ":-" also if it's empty. The others
+    // test the same way.
+    Default(bool, Vec<WordPart>),
+    // "=" and ":=" also assign the word to the parameter
+    Assign(bool, Vec<WordPart>),
+    // "?" and ":?" fail with the word as the message
+    Error(bool, Vec<WordPart>),
+    // "+" and ":+" use the word if the parameter is set
+    Alternative(bool, Vec<WordPart>),
+    // "${#name}", or the number of elements with "${#arr[@]}"
+    Length,
+    // "${!arr[@]}", the indexes or keys of an array
+    Keys,
+    // "#" removes the shortest matching prefix, "##" the longest one
+    RemovePrefix(bool, Vec<WordPart>),
+    // "%" and "%%" remove a suffix
+    RemoveSuffix(bool, Vec<WordPart>),
+    // "/pattern/replacement", and "//", "/#" or "/%"
+    Replace(ReplaceMode, Vec<WordPart>, Vec<WordPart>),
+    // ":offset" or ":offset:length", both arithmetic expressions
+    Substring(String, Option<String>),
+    // "^" and "," change the case of the first character, "^^" and ",," of all of them
+    Case(bool, bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaceMode {
+    First,
+    All,
+    // the pattern has to match at the start or at the end
+    Prefix,
+    Suffix,
+}
+
+fn write_joined(f: &mut std::fmt::Formatter<'_>, nodes: &[Node], sep: &str) -> std::fmt::Result {
+    for (idx, node) in nodes.iter().enumerate() {
+        if idx > 0 {
+            write!(f, "{}", sep)?;
+        }
+        write!(f, "{}", node)?;
+    }
+    Ok(())
+}
+
+fn write_redirects(f: &mut std::fmt::Formatter<'_>, redirects: &[Node]) -> std::fmt::Result {
+    redirects
+        .iter()
+        .try_for_each(|redirect| write!(f, " {}", redirect))
+}
+
+// renders the node back into shell syntax, e.g. for the job table
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(words, redirects) => {
+                write_joined(f, words, " ")?;
+                if !redirects.is_empty() {
+                    write!(f, " ")?;
+                }
+                write_joined(f, redirects, " ")
+            }
+            Self::Pipeline(commands) => write_joined(f, commands, " | "),
+            Self::And(left, right) => write!(f, "{} && {}", left, right),
+            Self::Or(left, right) => write!(f, "{} || {}", left, right),
+            Self::Word(word) if word.is_empty() || word.contains(char::is_whitespace) => {
+                write!(f, "'{}'", word)
+            }
+            Self::Word(word) => write!(f, "{}", word),
+            Self::ExpandableWord(parts) => parts.iter().try_for_each(|part| write!(f, "{}", part)),
+            Self::Assignment(name, value) => write!(f, "{}={}", name, value),
+            Self::AppendAssignment(name, value) => write!(f, "{}+={}", name, value),
+            Self::Array(elements) => {
+                write!(f, "(")?;
+                for (idx, (subscript, value)) in elements.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " ")?;
+                    }
+                    if let Some(subscript) = subscript {
+                        write!(f, "[{}]=", subscript)?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+            Self::RedirectAppend(1, target) => write!(f, ">> {}", target),
+            Self::RedirectAppend(fd, target) => write!(f, "{}>> {}", fd, target),
+            Self::RedirectWrite(1, target) => write!(f, "> {}", target),
+            Self::RedirectWrite(fd, target) => write!(f, "{}> {}", fd, target),
+            Self::RedirectRead(0, target) => write!(f, "< {}", target),
+            Self::RedirectRead(fd, target) => write!(f, "{}< {}", fd, target),
+            Self::RedirectReadWrite(0, target) => write!(f, "<> {}", target),
+            Self::RedirectReadWrite(fd, target) => write!(f, "{}<> {}", fd, target),
+            Self::RedirectDuplicate(fd, target) => write!(f, "{}>&{}", fd, target),
+            Self::RedirectHereDoc(0, delimiter, _) => write!(f, "<<{}", delimiter),
+            Self::RedirectHereDoc(fd, delimiter, _) => write!(f, "{}<<{}", fd, delimiter),
+            Self::RedirectHereString(0, word) => write!(f, "<<< {}", word),
+            Self::RedirectHereString(fd, word) => write!(f, "{}<<< {}", fd, word),
+            Self::CommandSequence(nodes) => write_joined(f, nodes, "; "),
+            Self::Background(node) => write!(f, "{} &", node),
+            Self::Subshell(body, redirects) => {
+                write!(f, "({})", body)?;
+                write_redirects(f, redirects)
+            }
+            Self::Group(body, redirects) => {
+                write!(f, "{{ {}; }}", body)?;
+                write_redirects(f, redirects)
+            }
+            Self::If(branches, else_body, redirects) => {
+                for (idx, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if idx == 0 { "if" } else { " elif" };
+                    write!(f, "{} {}; then {};", keyword, condition, body)?;
+                }
+                if let Some(body) = else_body {
+                    write!(f, " else {};", body)?;
+                }
+                write!(f, " fi")?;
+                write_redirects(f, redirects)
+            }
+            Self::While(condition, body, redirects) => {
+                write!(f, "while {}; do {}; done", condition, body)?;
+                write_redirects(f, redirects)
+            }
+            Self::Until(condition, body, redirects) => {
+                write!(f, "until {}; do {}; done", condition, body)?;
+                write_redirects(f, redirects)
+            }
+            Self::For(name, words, body, redirects)
+            | Self::Select(name, words, body, redirects) => {
+                let keyword = if matches!(self, Self::For(..)) {
+                    "for"
+                } else {
+                    "select"
+                };
+                write!(f, "{} {}", keyword, name)?;
+                if let Some(words) = words {
+                    write!(f, " in ")?;
+                    write_joined(f, words, " ")?;
+                }
+                write!(f, "; do {}; done", body)?;
+                write_redirects(f, redirects)
+            }
+            Self::ArithFor(init, condition, step, body, redirects) => {
+                write!(
+                    f,
+                    "for (({}; {}; {})); do {}; done",
+                    init, condition, step, body
+                )?;
+                write_redirects(f, redirects)
+            }
+            Self::Case(word, items, redirects) => {
+                write!(f, "case {} in", word)?;
+                for item in items {
+                    write!(f, " ")?;
+                    write_joined(f, &item.patterns, " | ")?;
+                    write!(f, ") {} {}", item.body, item.terminator)?;
+                }
+                write!(f, " esac")?;
+                write_redirects(f, redirects)
+            }
+            Self::FunctionDef(name, body) => write!(f, "{}() {}", name, body),
+            Self::Arithmetic(expression, redirects) => {
+                write!(f, "(({}))", expression)?;
+                write_redirects(f, redirects)
+            }
+        }
+    }
+}
+
+fn write_parts(f: &mut std::fmt::Formatter<'_>, parts: &[WordPart]) -> std::fmt::Result {
+    parts.iter().try_for_each(|part| write!(f, "{}", part))
+}
+
+// renders the operator that follows the name, except for "${#name}" and "${!name[@]}"
+// where it comes before
+impl Display for ParameterOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let colon = |colon: &bool| if *colon { ":" } else { "" };
+        let double = |double: &bool, operator: &str| {
+            if *double {
+                operator.repeat(2)
+            } else {
+                operator.to_string()
+            }
+        };
+        match self {
+            Self::Default(test_empty, word) => {
+                write!(f, "{}-", colon(test_empty))?;
+                write_parts(f, word)
+            }
+            Self::Assign(test_empty, word) => {
+                write!(f, "{}=", colon(test_empty))?;
+                write_parts(f, word)
+            }
+            Self::Error(test_empty, word) => {
+                write!(f, "{}?", colon(test_empty))?;
+                write_parts(f, word)
+            }
+            Self::Alternative(test_empty, word) => {
+                write!(f, "{}+", colon(test_empty))?;
+                write_parts(f, word)
+            }
+            Self::Length | Self::Keys => Ok(()),
+            Self::RemovePrefix(longest, pattern) => {
+                write!(f, "{}", double(longest, "#"))?;
+                write_parts(f, pattern)
+            }
+            Self::RemoveSuffix(longest, pattern) => {
+                write!(f, "{}", double(longest, "%"))?;
+                write_parts(f, pattern)
+            }
+            Self::Replace(mode, pattern, replacement) => {
+                let operator = match mode {
+                    ReplaceMode::First => "/",
+                    ReplaceMode::All => "//",
+                    ReplaceMode::Prefix => "/#",
+                    ReplaceMode::Suffix => "/%",
+                };
+                write!(f, "{}", operator)?;
+                write_parts(f, pattern)?;
+                write!(f, "/")?;
+                write_parts(f, replacement)
+            }
+            Self::Substring(offset, length) => {
+                write!(f, ":{}", offset)?;
+                match length {
+                    Some(length) => write!(f, ":{}", length),
+                    None => Ok(()),
+                }
+            }
+            Self::Case(upper, all) => write!(f, "{}", double(all, if *upper { "^" } else { "," })),
+        }
+    }
+}
+
+impl Display for WordPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Literal(literal) => write!(f, "{}", literal),
+            Self::Variable(name) => write!(f, "${{{}}}", name),
+            Self::CommandSubstitution(node) => write!(f, "$({})", node),
+            Self::Glob(glob) => write!(f, "{}", glob),
+            Self::Tilde(name) => write!(f, "~{}", name),
+            Self::Arithmetic(expression) => write!(f, "$(({}))", expression),
+            Self::Parameter(name, ParameterOperator::Length) => write!(f, "${{#{}}}", name),
+            Self::Parameter(name, ParameterOperator::Keys) => write!(f, "${{!{}}}", name),
+            Self::Parameter(name, operator) => write!(f, "${{{}{}}}", name, operator),
+            Self::Brace(alternatives) => {
+                write!(f, "{{")?;
+                for (idx, alternative) in alternatives.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    alternative
+                        .iter()
+                        .try_for_each(|part| write!(f, "{}", part))?;
+                }
+                write!(f, "}}")
+            }
+            Self::DoubleQuoted(parts) => {
+                write!(f, "\"")?;
+                parts.iter().try_for_each(|part| write!(f, "{}", part))?;
+                write!(f, "\"")
+            }
+        }
+    }
 }
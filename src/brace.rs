@@ -0,0 +1,88 @@
+use crate::ast::WordPart;
+
+// expands the braces in a word into the words they stand for, as in "file{,.bak}" or
+// "{a,b}{1,2}". Braces are expanded before anything else, so each alternative is a word.
+pub fn brace_expansion(parts: &[WordPart]) -> Vec<Vec<WordPart>> {
+    let mut words = vec![vec![]];
+    for part in parts {
+        match part {
+            WordPart::Brace(alternatives) => {
+                let expanded: Vec<Vec<WordPart>> = alternatives
+                    .iter()
+                    .flat_map(|alternative| brace_expansion(alternative))
+                    .collect();
+                words = words
+                    .iter()
+                    .flat_map(|word| {
+                        expanded.iter().map(move |alternative| {
+                            let mut word = word.clone();
+                            word.extend(alternative.iter().cloned());
+                            word
+                        })
+                    })
+                    .collect();
+            }
+            part => words.iter_mut().for_each(|word| word.push(part.clone())),
+        }
+    }
+    words
+}
+
+// the words of a sequence expression like "1..10", "a..e" or "01..10..2" without its braces.
+// Numbers starting with a zero are padded to the same width.
+pub fn sequence(body: &str) -> Option<Vec<String>> {
+    let (start, end, step) = match body.split("..").collect::<Vec<_>>().as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (*start, *end, step.parse::<i64>().ok()?.unsigned_abs()),
+        _ => return None,
+    };
+    let step = step.max(1);
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |number: &str| {
+            let digits = number.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Some(
+            range(first, last, step)?
+                .map(|number| format!("{:0width$}", number, width = width))
+                .collect(),
+        );
+    }
+    let (first, last) = (letter(start)?, letter(end)?);
+    Some(
+        range(first as i64, last as i64, step)?
+            .map(|chr| char::from(chr as u8).to_string())
+            .collect(),
+    )
+}
+
+// the most words a sequence expands to, longer ones are left as they are
+const MAX_SEQUENCE_LENGTH: u64 = 1_000_000;
+
+// counts down if the sequence ends below its start. None if it's too long.
+fn range(first: i64, last: i64, step: u64) -> Option<impl Iterator<Item = i64>> {
+    let count = first.abs_diff(last) / step;
+    if count >= MAX_SEQUENCE_LENGTH {
+        return None;
+    }
+    // a step may not fit into an i64, the numbers in between the ends always do
+    let step = if first <= last {
+        step as i128
+    } else {
+        -(step as i128)
+    };
+    Some((0..=count).map(move |idx| (first as i128 + idx as i128 * step) as i64))
+}
+
+fn letter(string: &str) -> Option<char> {
+    let mut chars = string.chars();
+    match (chars.next(), chars.next()) {
+        (Some(chr), None) if chr.is_ascii_alphabetic() => Some(chr),
+        _ => None,
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fs::{read_dir, File};
+use std::io::{stdout, Error, Result, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+// what a file descriptor of a command refers to
+#[derive(Clone, Debug)]
+enum FdTarget {
+    // the descriptor with this number in the shell
+    Shell(RawFd),
+    Open(Rc<OwnedFd>),
+    Closed,
+}
+
+// the file descriptors a command gets. Redirects are applied to the table from left to right,
+// so "2>&1 >file" and ">file 2>&1" end up different, and the child is set up from it on spawn.
+#[derive(Debug)]
+pub struct FdTable {
+    fds: BTreeMap<RawFd, FdTarget>,
+}
+
+impl FdTable {
+    // stdin and stdout are the pipes of the pipeline, if there are any
+    pub fn new(stdin: Option<OwnedFd>, stdout: Option<OwnedFd>) -> Self {
+        let mut fds = BTreeMap::new();
+        for (fd, pipe) in [(0, stdin), (1, stdout)] {
+            let target = pipe.map_or(FdTarget::Shell(fd), |pipe| FdTarget::Open(Rc::new(pipe)));
+            fds.insert(fd, target);
+        }
+        Self { fds }
+    }
+
+    fn get(&self, fd: RawFd) -> FdTarget {
+        self.fds.get(&fd).cloned().unwrap_or(FdTarget::Shell(fd))
+    }
+
+    // points fd at a file or pipe
+    pub fn open(&mut self, fd: RawFd, file: impl Into<OwnedFd>) {
+        self.fds.insert(fd, FdTarget::Open(Rc::new(file.into())));
+    }
+
+    pub fn close(&mut self, fd: RawFd) {
+        self.fds.insert(fd, FdTarget::Closed);
+    }
+
+    // makes fd refer to the same file as source, fails if source isn't open
+    pub fn duplicate(&mut self, fd: RawFd, source: RawFd) -> Result<()> {
+        let target = self.get(source);
+        let open = match target {
+            FdTarget::Shell(shell_fd) => inherited(shell_fd),
+            FdTarget::Open(_) => true,
+            FdTarget::Closed => false,
+        };
+        if !open {
+            return Err(Error::from_raw_os_error(libc::EBADF));
+        }
+        self.fds.insert(fd, target);
+        Ok(())
+    }
+
+    // sets up the file descriptors of a command that is about to be spawned
+    pub fn apply(&self, command: &mut Command) -> Result<()> {
+        // the copies handed to the child are numbered above every descriptor in the table,
+        // so moving one into place can't overwrite another that is still needed
+        let lowest = self.fds.keys().max().map_or(3, |fd| (fd + 1).max(3));
+        let mut moves = vec![];
+        let mut closes = vec![];
+        for (&fd, target) in self.fds.iter() {
+            let source = match target {
+                FdTarget::Shell(shell_fd) if *shell_fd == fd => continue,
+                FdTarget::Shell(shell_fd) => dup_above(*shell_fd, lowest)?,
+                FdTarget::Open(file) => dup_above(file.as_raw_fd(), lowest)?,
+                FdTarget::Closed => {
+                    closes.push(fd);
+                    continue;
+                }
+            };
+            match fd {
+                0 => command.stdin(Stdio::from(source)),
+                1 => command.stdout(Stdio::from(source)),
+                2 => command.stderr(Stdio::from(source)),
+                _ => {
+                    moves.push((source, fd));
+                    continue;
+                }
+            };
+        }
+        if moves.is_empty() && closes.is_empty() {
+            return Ok(());
+        }
+        // runs after the standard streams are set up in the child
+        unsafe {
+            command.pre_exec(move || {
+                for (source, fd) in moves.iter() {
+                    if libc::dup2(source.as_raw_fd(), *fd) == -1 {
+                        return Err(Error::last_os_error());
+                    }
+                }
+                for fd in closes.iter() {
+                    libc::close(*fd);
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    // points the descriptors of the shell itself at their targets, for commands that run in the
+    // shell like "{ a; b; } > log". They are put back when the returned value is dropped.
+    pub fn apply_to_shell(&self) -> Result<SavedFds> {
+        let _ = stdout().flush();
+        let lowest = self.fds.keys().max().map_or(3, |fd| (fd + 1).max(3));
+        // every source is copied before any descriptor changes, as in apply
+        let mut targets = vec![];
+        for (&fd, target) in self.fds.iter() {
+            let source = match target {
+                FdTarget::Shell(shell_fd) if *shell_fd == fd => continue,
+                FdTarget::Shell(shell_fd) => Some(dup_above(*shell_fd, lowest)?),
+                FdTarget::Open(file) => Some(dup_above(file.as_raw_fd(), lowest)?),
+                FdTarget::Closed => None,
+            };
+            targets.push((fd, source));
+        }
+        let mut saved = SavedFds { fds: vec![] };
+        for (fd, source) in targets {
+            let previous = if is_open(fd) {
+                Some(dup_above(fd, lowest)?)
+            } else {
+                None
+            };
+            saved.fds.push((fd, previous));
+            match source {
+                Some(source) if unsafe { libc::dup2(source.as_raw_fd(), fd) } == -1 => {
+                    return Err(Error::last_os_error())
+                }
+                Some(_) => (),
+                None => unsafe {
+                    libc::close(fd);
+                },
+            }
+        }
+        Ok(saved)
+    }
+}
+
+// what the descriptors of the shell referred to before apply_to_shell
+#[derive(Debug)]
+pub struct SavedFds {
+    fds: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        // output of builtins may still be buffered for the redirected stdout
+        let _ = stdout().flush();
+        for (fd, previous) in self.fds.iter().rev() {
+            match previous {
+                Some(previous) => unsafe { libc::dup2(previous.as_raw_fd(), *fd) },
+                None => unsafe { libc::close(*fd) },
+            };
+        }
+    }
+}
+
+// lets go of the descriptors the shell opened for itself, which are the ones closed on exec.
+// A forked copy of the shell that doesn't exec would otherwise keep pipes of others open.
+// They may still be owned by values of the shell, so instead of closing them they're pointed
+// at /dev/null, which their owners can close as usual.
+pub fn release_shell_fds() {
+    let Ok(null) = File::open("/dev/null") else {
+        return;
+    };
+    let Ok(entries) = read_dir("/dev/fd") else {
+        return;
+    };
+    let fds: Vec<RawFd> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        if fd != null.as_raw_fd() && is_open(fd) && !inherited(fd) {
+            unsafe { libc::dup3(null.as_raw_fd(), fd, libc::O_CLOEXEC) };
+        }
+    }
+}
+
+fn is_open(fd: RawFd) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+}
+
+// duplicates fd to the lowest free descriptor that is at least lowest, closed on exec
+fn dup_above(fd: RawFd, lowest: RawFd) -> Result<OwnedFd> {
+    let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, lowest) };
+    if new_fd == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(new_fd) })
+}
+
+// whether children of the shell get the descriptor, the shell's own files are closed on exec
+fn inherited(fd: RawFd) -> bool {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    flags != -1 && flags & libc::FD_CLOEXEC == 0
+}
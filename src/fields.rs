@@ -0,0 +1,89 @@
+use crate::pattern::escape;
+use std::mem::take;
+
+// an argument a word expands into
+#[derive(Debug, PartialEq)]
+pub struct Field {
+    pub text: String,
+    // the field as a pattern with its quoted characters escaped, if it has unquoted glob characters
+    pub pattern: Option<String>,
+}
+
+// collects the arguments a word expands into
+#[derive(Debug, Default)]
+pub struct Fields {
+    fields: Vec<Field>,
+    current: String,
+    pattern: String,
+    glob: bool,
+    // the current field is kept even if it's empty, because part of it was quoted
+    keep_empty: bool,
+}
+
+impl Fields {
+    // adds literal text, which only matches itself when globbing
+    pub fn push_str(&mut self, string: &str) {
+        self.current.push_str(string);
+        self.pattern.push_str(&escape(string));
+    }
+
+    // adds unquoted glob characters, like "*" or "[a-z]"
+    pub fn push_glob(&mut self, glob: &str) {
+        self.current.push_str(glob);
+        self.pattern.push_str(glob);
+        self.glob = true;
+    }
+
+    pub fn keep_empty(&mut self) {
+        self.keep_empty = true;
+    }
+
+    // ends the current field, which is dropped if it's empty and nothing in it was quoted
+    pub fn end_field(&mut self) {
+        if self.keep_empty || !self.current.is_empty() {
+            let pattern = take(&mut self.pattern);
+            self.fields.push(Field {
+                text: take(&mut self.current),
+                pattern: self.glob.then_some(pattern),
+            });
+        }
+        self.pattern.clear();
+        self.glob = false;
+        self.keep_empty = false;
+    }
+
+    // adds the result of an unquoted expansion, split into fields at the characters in ifs.
+    // A run of whitespace is one delimiter, and so is whitespace around another delimiter.
+    // Glob characters in the result are active, as in "X='*.rs'; ls $X".
+    pub fn push_split(&mut self, string: &str, ifs: &str) {
+        // whether whitespace just ended a field, which a following delimiter belongs to
+        let mut delimited = false;
+        for chr in string.chars() {
+            if !ifs.contains(chr) {
+                self.current.push(chr);
+                if chr == '\\' {
+                    self.pattern.push('\\');
+                }
+                self.pattern.push(chr);
+                self.glob |= "*?[".contains(chr);
+                delimited = false;
+            } else if chr.is_whitespace() {
+                if self.keep_empty || !self.current.is_empty() {
+                    self.end_field();
+                    delimited = true;
+                }
+            } else {
+                if !delimited {
+                    self.keep_empty = true;
+                    self.end_field();
+                }
+                delimited = false;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<Field> {
+        self.end_field();
+        self.fields
+    }
+}
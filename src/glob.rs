@@ -0,0 +1,124 @@
+use crate::pattern::Pattern;
+use std::fs::{read_dir, symlink_metadata};
+use std::path::Path;
+
+// shell options for pathname expansion, set with the shopt builtin
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlobOptions {
+    // patterns without matches expand to nothing instead of themselves
+    pub nullglob: bool,
+    // patterns without matches are an error
+    pub failglob: bool,
+    // "*" and "?" also match files starting with "."
+    pub dotglob: bool,
+}
+
+impl GlobOptions {
+    pub fn names() -> [&'static str; 3] {
+        ["dotglob", "failglob", "nullglob"]
+    }
+
+    pub fn option_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "failglob" => Some(&mut self.failglob),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
+
+// the paths matching a pattern, sorted. Each component between slashes is matched against
+// the entries of a directory, and a "**" component matches any number of directories.
+pub fn glob(pattern: &str, options: GlobOptions) -> Vec<String> {
+    let mut paths = vec![if pattern.starts_with('/') {
+        "/".to_string()
+    } else {
+        String::new()
+    }];
+    let components: Vec<&str> = pattern.split('/').filter(|comp| !comp.is_empty()).collect();
+    for (idx, component) in components.iter().enumerate() {
+        let last = idx == components.len() - 1;
+        paths = paths
+            .iter()
+            .flat_map(|base| expand_component(base, component, last, options))
+            .collect();
+    }
+    // "*/" only matches directories
+    if pattern.ends_with('/') && !components.is_empty() {
+        paths = paths
+            .into_iter()
+            .filter(|path| Path::new(path).is_dir())
+            .map(|path| path + "/")
+            .collect();
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn expand_component(base: &str, component: &str, last: bool, options: GlobOptions) -> Vec<String> {
+    if component == "**" {
+        let mut dirs = vec![base.to_string()];
+        collect_recursive(base, last, options, &mut dirs);
+        if last {
+            // a trailing "**" matches files as well, but not the directory it starts from
+            dirs.remove(0);
+        }
+        return dirs;
+    }
+    let pattern = Pattern::new(component);
+    if pattern.is_literal() {
+        let path = join(base, &pattern.literal());
+        return match symlink_metadata(&path) {
+            Ok(_) => vec![path],
+            Err(_) => vec![],
+        };
+    }
+    let Ok(entries) = read_dir(if base.is_empty() { "." } else { base }) else {
+        return vec![];
+    };
+    let hidden = options.dotglob || pattern.starts_with_dot();
+    let mut paths = vec![];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if (name.starts_with('.') && !hidden) || !pattern.matches(&name) {
+            continue;
+        }
+        let path = join(base, &name);
+        if last || Path::new(&path).is_dir() {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+// adds the entries below the directory, only directories unless files are wanted as well.
+// Symbolic links to directories aren't followed, so a link cycle can't recurse forever.
+fn collect_recursive(base: &str, files: bool, options: GlobOptions, paths: &mut Vec<String>) {
+    let Ok(entries) = read_dir(if base.is_empty() { "." } else { base }) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !options.dotglob {
+            continue;
+        }
+        let path = join(base, &name);
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if is_dir || files {
+            paths.push(path.clone());
+        }
+        if is_dir {
+            collect_recursive(&path, files, options, paths);
+        }
+    }
+}
+
+fn join(base: &str, name: &str) -> String {
+    match base {
+        "" => name.to_string(),
+        "/" => format!("/{}", name),
+        _ => format!("{}/{}", base, name),
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SIGINT_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+impl Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Running => write!(f, "Running"),
+            Self::Stopped => write!(f, "Stopped"),
+            Self::Done(0) => write!(f, "Done"),
+            Self::Done(code) => write!(f, "Exit {}", code),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProcessState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    // 0 if the job shares the process group of the shell
+    pub pgid: i32,
+    pub command: String,
+    pub stop_reported: bool,
+    pids: Vec<i32>,
+    states: Vec<ProcessState>,
+}
+
+impl Job {
+    pub fn new(pgid: i32, pids: Vec<i32>, command: String) -> Self {
+        let states = vec![ProcessState::Running; pids.len()];
+        Self {
+            id: 0,
+            pgid,
+            command,
+            stop_reported: false,
+            pids,
+            states,
+        }
+    }
+
+    // a job is done once every process in it is done, its status is the last one's
+    pub fn state(&self) -> JobState {
+        if self.states.contains(&ProcessState::Running) {
+            JobState::Running
+        } else if self.states.contains(&ProcessState::Stopped) {
+            JobState::Stopped
+        } else {
+            match self.states.last() {
+                Some(ProcessState::Done(code)) => JobState::Done(*code),
+                _ => JobState::Done(0),
+            }
+        }
+    }
+
+    pub fn last_pid(&self) -> i32 {
+        self.pids.last().copied().unwrap_or(self.pgid)
+    }
+
+    // reaps the processes of the job that exited or stopped, without blocking
+    pub fn poll(&mut self) {
+        for idx in 0..self.pids.len() {
+            if self.states[idx] == ProcessState::Running {
+                self.wait_process(idx, libc::WNOHANG);
+            }
+        }
+    }
+
+    // blocks until the first running process of the job exits or stops.
+    // Returns false if a signal interrupted the wait.
+    pub fn wait(&mut self) -> bool {
+        match self
+            .states
+            .iter()
+            .position(|state| *state == ProcessState::Running)
+        {
+            Some(idx) => self.wait_process(idx, 0),
+            None => true,
+        }
+    }
+
+    fn wait_process(&mut self, idx: usize, options: i32) -> bool {
+        let mut status = 0;
+        let pid = self.pids[idx];
+        let res = unsafe { libc::waitpid(pid, &mut status, options | libc::WUNTRACED) };
+        if res == pid && libc::WIFSTOPPED(status) {
+            self.states[idx] = ProcessState::Stopped;
+        } else if res == pid {
+            self.states[idx] = ProcessState::Done(wait_status_code(status));
+        } else if res == -1 && Error::last_os_error().kind() == ErrorKind::Interrupted {
+            return false;
+        } else if res == -1 {
+            // the process was already reaped elsewhere, so its status is lost
+            self.states[idx] = ProcessState::Done(127);
+        }
+        true
+    }
+
+    pub fn signal(&self, signal: i32) -> bool {
+        if self.pgid > 0 {
+            return unsafe { libc::kill(-self.pgid, signal) == 0 };
+        }
+        let running = self.pids.iter().zip(self.states.iter());
+        running
+            .filter(|(_, state)| !matches!(state, ProcessState::Done(_)))
+            .all(|(pid, _)| unsafe { libc::kill(*pid, signal) == 0 })
+    }
+
+    // continues the stopped processes of the job
+    pub fn resume(&mut self) -> bool {
+        for state in self.states.iter_mut() {
+            if *state == ProcessState::Stopped {
+                *state = ProcessState::Running;
+            }
+        }
+        self.stop_reported = false;
+        self.signal(libc::SIGCONT)
+    }
+}
+
+impl Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state();
+        write!(
+            f,
+            "[{}]  {:<24}{}",
+            self.id,
+            state.to_string(),
+            self.command
+        )?;
+        if state == JobState::Running {
+            write!(f, " &")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    // gives the job the next free id and returns its index
+    pub fn add(&mut self, mut job: Job) -> usize {
+        job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(job);
+        self.jobs.len() - 1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    // finds a job by "%n", "%%", "%+" or the pid of one of its processes
+    pub fn find(&self, spec: &str) -> Option<usize> {
+        match spec {
+            "%" | "%%" | "%+" => self.jobs.len().checked_sub(1),
+            _ => match spec.strip_prefix('%') {
+                Some(id) => {
+                    let id = id.parse::<usize>().ok()?;
+                    self.jobs.iter().position(|job| job.id == id)
+                }
+                None => {
+                    let pid = spec.parse::<i32>().ok()?;
+                    self.jobs.iter().position(|job| job.pids.contains(&pid))
+                }
+            },
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> &Job {
+        &self.jobs[idx]
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> &mut Job {
+        &mut self.jobs[idx]
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Job {
+        self.jobs.remove(idx)
+    }
+
+    pub fn poll(&mut self) {
+        for job in self.jobs.iter_mut() {
+            job.poll();
+        }
+    }
+
+    pub fn remove_finished(&mut self) -> Vec<Job> {
+        let (finished, remaining) = self
+            .jobs
+            .drain(..)
+            .partition(|job| matches!(job.state(), JobState::Done(_)));
+        self.jobs = remaining;
+        finished
+    }
+
+    // stopped jobs that haven't been reported yet, marking them as reported
+    pub fn newly_stopped(&mut self) -> Vec<&Job> {
+        let mut stopped = vec![];
+        for job in self.jobs.iter_mut() {
+            if job.state() == JobState::Stopped && !job.stop_reported {
+                job.stop_reported = true;
+                stopped.push(&*job);
+            }
+        }
+        stopped
+    }
+}
+
+// exit code of a raw wait status, 128 + N for processes killed by signal N
+pub fn wait_status_code(status: i32) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
+
+pub fn signal_number(name: &str) -> Option<i32> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Some(number);
+    }
+    let signal = match name.strip_prefix("SIG").unwrap_or(name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "PIPE" => libc::SIGPIPE,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CHLD" => libc::SIGCHLD,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "TTIN" => libc::SIGTTIN,
+        "TTOU" => libc::SIGTTOU,
+        _ => return None,
+    };
+    Some(signal)
+}
+
+// signals the shell ignores under job control, which children need to get back
+const JOB_CONTROL_SIGNALS: [i32; 4] = [libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+extern "C" fn handle_sigint(_: i32) {
+    SIGINT_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+// records SIGINT instead of exiting. Without SA_RESTART a blocking waitpid returns early,
+// so the shell notices the interrupt right away. Exec resets the handler in children.
+pub fn install_sigint_handler() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sigint as *const () as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+// returns whether SIGINT was received since the last call
+pub fn take_interrupt() -> bool {
+    SIGINT_RECEIVED.swap(false, Ordering::SeqCst)
+}
+
+// puts the shell in its own process group in the foreground of the terminal
+// and returns the process group id
+pub fn init_job_control() -> i32 {
+    unsafe {
+        // a shell started in the background waits until it is brought to the foreground
+        while libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() {
+            libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+        }
+        for signal in JOB_CONTROL_SIGNALS {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+        let pgid = libc::getpid();
+        libc::setpgid(pgid, pgid);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        pgid
+    }
+}
+
+pub fn give_terminal(pgid: i32) {
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid) };
+}
+
+// runs in the child between fork and exec, so it may only make async-signal-safe calls
+pub fn reset_child_signals(take_terminal: bool) {
+    unsafe {
+        // the child may get the terminal before it gets here, so ctrl+c that reached the
+        // handler it got from the shell kills it all the same
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        if SIGINT_RECEIVED.load(Ordering::SeqCst) {
+            libc::raise(libc::SIGINT);
+        }
+        if take_terminal {
+            // SIGTTOU is still ignored here, so this can't stop the child
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        }
+        for signal in JOB_CONTROL_SIGNALS {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+}
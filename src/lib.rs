@@ -1,26 +1,56 @@
-use std::env::set_current_dir;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env::{current_dir, set_current_dir};
+use std::ffi::{CStr, CString};
 use std::fmt::Display;
 use std::fs::OpenOptions;
-use std::os::unix::process::ExitStatusExt;
+use std::io::{pipe, stdin, stdout, IsTerminal, PipeReader, Read, Write};
+use std::io::{Error as IoError, ErrorKind};
+use std::os::fd::OwnedFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::Path;
 use std::process::ExitStatus;
-use std::process::{exit, Child, Command, Output, Stdio};
+use std::process::{exit, Child, Command, Output};
 use std::str::FromStr;
-use std::sync::mpsc;
-use std::thread::sleep;
-use std::time::Duration;
+use std::thread;
 
 // TODO best way to handle namespaces?
+pub mod arith;
 pub mod ast;
+pub mod brace;
+pub mod fds;
+pub mod fields;
+pub mod glob;
+pub mod jobs;
+pub mod parameter;
 pub mod parser;
+pub mod pattern;
 pub mod scanner;
 pub mod token;
-use crate::ast::Node;
+pub mod variables;
+use crate::arith::{evaluate, ArithEnv};
+use crate::ast::{CaseItem, CaseTerminator, Node, ParameterOperator, WordPart};
+use crate::fds::{release_shell_fds, FdTable};
+use crate::fields::{Field, Fields};
+use crate::glob::{glob, GlobOptions};
+use crate::jobs::{
+    give_terminal, init_job_control, install_sigint_handler, reset_child_signals, signal_number,
+    take_interrupt, wait_status_code, Job, JobState, Jobs,
+};
+use crate::parameter::{change_case, remove_prefix, remove_suffix, replace, substring};
+use crate::pattern::{escape, Pattern};
+use crate::scanner::Scanner;
+use crate::variables::{is_valid_name, split_subscript, Subscript, Variables};
 #[derive(Debug)]
 enum InterpretErr {
     RuntimeError(&'static str),
     Interrupt(&'static str),
     ExitStatusFailure(&'static str), // for crsh builtins
+    // "break N" and "continue N", unwinding to the Nth enclosing loop
+    Break(usize),
+    Continue(usize),
+    // "return N" in a function
+    Return(i32),
 }
 
 impl Display for InterpretErr {
@@ -29,12 +59,76 @@ impl Display for InterpretErr {
             Self::RuntimeError(msg) => write!(f, "Runtime Error: {}", msg),
             Self::Interrupt(msg) => write!(f, "Interrupt: {}", msg),
             Self::ExitStatusFailure(msg) => write!(f, "ExitStatusFailure: {}", msg),
+            Self::Break(count) => write!(f, "Break: {}", count),
+            Self::Continue(count) => write!(f, "Continue: {}", count),
+            Self::Return(code) => write!(f, "Return: {}", code),
         }
     }
 }
 
+// a pipeline stage is either a spawned child, a forked copy of the shell running a
+// subshell or group, or a builtin that already finished
+enum Stage {
+    Running(Child),
+    Forked(i32),
+    Finished(i32),
+}
+
+// the commands that run in the shell itself
+const BUILTINS: [&str; 16] = [
+    "cd", "exit", "export", "unset", "jobs", "fg", "bg", "wait", "kill", "shopt", "break",
+    "continue", "local", "declare", "return", "let",
+];
+
+// how deep functions may call each other, before the shell runs out of stack
+const MAX_FUNCTION_DEPTH: usize = 500;
+
+// a call of a shell function
+#[derive(Clone, Copy)]
+struct FunctionCall<'a> {
+    body: &'a Node,
+    args: &'a [String],
+    assignments: &'a [(String, String)],
+}
+
+// an assignment with its value expanded. The elements of an array keep their subscripts,
+// which are evaluated when it's assigned.
+struct Assignment {
+    name: String,
+    append: bool,
+    value: AssignedValue,
+}
+
+enum AssignedValue {
+    Scalar(String),
+    Array(Vec<(Option<String>, String)>),
+}
+
+// process group a spawned child joins, 0 starts a new one led by the child
+#[derive(Clone, Copy)]
+struct ProcessGroup {
+    pgid: i32,
+    foreground: bool,
+}
+
 pub struct Crsh {
-    sigint_receiver: mpsc::Receiver<bool>,
+    // interactive shells put each pipeline in its own process group and hand it the terminal
+    job_control: bool,
+    shell_pgid: i32,
+    variables: Variables,
+    last_status: i32,
+    jobs: Jobs,
+    last_background: Option<i32>,
+    // "$0" followed by the positional parameters
+    positional: Vec<String>,
+    // exit code of the last command substitution while expanding the current command
+    substitution_status: Option<i32>,
+    glob_options: GlobOptions,
+    // number of loops the running command is in, which "break" and "continue" can leave
+    loop_depth: usize,
+    functions: HashMap<String, Node>,
+    // number of function calls the running command is in
+    function_depth: usize,
 }
 
 impl Default for Crsh {
@@ -44,217 +138,1975 @@ impl Default for Crsh {
 }
 
 impl Crsh {
+    // uses job control if stdin is a terminal
     pub fn new() -> Self {
-        let (sender, receiver): (mpsc::Sender<bool>, mpsc::Receiver<bool>) = mpsc::channel();
-        ctrlc::set_handler(move || {
-            sender.send(true).unwrap();
-        })
-        .expect("Error setting ctrl-c handler");
-        Self {
-            sigint_receiver: receiver,
+        Self::with_job_control(stdin().is_terminal())
+    }
+
+    pub fn with_job_control(job_control: bool) -> Self {
+        install_sigint_handler();
+        let shell_pgid = if job_control {
+            init_job_control()
+        } else {
+            unsafe { libc::getpgrp() }
+        };
+        let mut crsh = Self {
+            job_control,
+            shell_pgid,
+            variables: Variables::from_env(),
+            last_status: 0,
+            jobs: Jobs::default(),
+            last_background: None,
+            positional: vec!["crsh".to_string()],
+            substitution_status: None,
+            glob_options: GlobOptions::default(),
+            loop_depth: 0,
+            functions: HashMap::new(),
+            function_depth: 0,
+        };
+        crsh.update_pwd();
+        crsh
+    }
+
+    // sets "$0" to the first argument and "$1", "$2", ... to the rest
+    pub fn set_positional(&mut self, args: Vec<String>) {
+        if !args.is_empty() {
+            self.positional = args;
         }
     }
 
+    // the prompt for the lines after the first one of a command, "> " unless $PS2 is set
+    pub fn continuation_prompt(&self) -> String {
+        self.variables.get("PS2").unwrap_or("> ").to_string()
+    }
+
+    // exit code of the last pipeline, 128 + N if it was killed by signal N
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
     pub fn execute(&mut self, node: Node) -> Result<Output, String> {
-        // TODO catch interrupt error here
-        self.clear_handler();
+        // forget about ctrl+c presses at the prompt
+        take_interrupt();
+        match self.run(node) {
+            // ctrl+c stops the rest of the line, as if it was killed by SIGINT
+            Err(InterpretErr::Interrupt(_)) => {
+                self.last_status = 128 + libc::SIGINT;
+                Ok(Self::new_empty_output(self.last_status))
+            }
+            res => res.map_err(|err| format!("{}", err)),
+        }
+    }
+
+    fn run(&mut self, node: Node) -> Result<Output, InterpretErr> {
         match node {
-            Node::Pipeline(commands) => self
-                .pipeline_command(commands)
-                .map_err(|err| format!("{}", err)),
-            Node::CommandSequence(command_seq) => self
-                .command_sequence(command_seq)
-                .map_err(|err| format!("{}", err)),
-            _ => Err("Unexpected starting node".to_string()),
+            Node::Pipeline(_) | Node::And(_, _) | Node::Or(_, _) => self.and_or(node),
+            Node::CommandSequence(command_seq) => self.command_sequence(command_seq),
+            Node::Background(node) => self.background(*node),
+            _ => Err(InterpretErr::RuntimeError("Unexpected starting node")),
         }
     }
 
-    fn clear_handler(&mut self) {
-        // clear the channel with the sigint handler
-        while self.sigint_receiver.try_recv().is_ok() {}
+    // prints background jobs that stopped, and forgets the ones that finished since the last call
+    pub fn report_jobs(&mut self) {
+        self.jobs.poll();
+        for job in self.jobs.newly_stopped() {
+            println!("{}", job);
+        }
+        for job in self.jobs.remove_finished() {
+            println!("{}", job);
+        }
     }
 
     fn command_sequence(&mut self, command_seq: Vec<Node>) -> Result<Output, InterpretErr> {
         let mut res = Ok(Self::new_empty_output(0));
         // TODO support command in command sequence
         for command in command_seq {
-            res = match command {
-                Node::Pipeline(commands) => match self.pipeline_command(commands) {
-                    Ok(output) => Ok(output),
-                    Err(InterpretErr::ExitStatusFailure(_)) => Ok(Self::new_empty_output(1)),
-                    Err(InterpretErr::Interrupt(_)) => Ok(Self::new_empty_output(130)),
-                    Err(InterpretErr::RuntimeError(msg)) => {
-                        return Err(InterpretErr::RuntimeError(msg))
-                    }
-                },
-                _ => Err(InterpretErr::RuntimeError(
-                    "Unexpected node in command sequence",
-                )),
+            let command_res = match command {
+                Node::Background(node) => self.background(*node),
+                node => self.and_or(node),
+            };
+            res = match command_res {
+                Ok(output) => Ok(output),
+                Err(InterpretErr::ExitStatusFailure(_)) => Ok(Self::new_empty_output(1)),
+                // ctrl+c stops the rest of the list as well, and so does leaving a loop
+                Err(err) => return Err(err),
             };
         }
         res
     }
 
+    fn and_or(&mut self, node: Node) -> Result<Output, InterpretErr> {
+        match node {
+            // failures already reported their error, and only set the status
+            Node::Pipeline(commands) => match self.pipeline_command(commands) {
+                Err(InterpretErr::ExitStatusFailure(_)) => Ok(Self::new_empty_output(1)),
+                res => res,
+            },
+            Node::And(left, right) => {
+                let output = self.and_or(*left)?;
+                if output.status.success() {
+                    self.and_or(*right)
+                } else {
+                    Ok(output)
+                }
+            }
+            Node::Or(left, right) => {
+                let output = self.and_or(*left)?;
+                if output.status.success() {
+                    Ok(output)
+                } else {
+                    self.and_or(*right)
+                }
+            }
+            _ => Err(InterpretErr::RuntimeError(
+                "Unexpected node in command sequence",
+            )),
+        }
+    }
+
+    fn background(&mut self, node: Node) -> Result<Output, InterpretErr> {
+        let command = node.to_string();
+        let pids = match node {
+            Node::Pipeline(commands) => self.spawn_pipeline(&commands, true)?.0,
+            node => vec![self.fork_list(node)?],
+        };
+        // a pipeline of builtins already finished, so there is no job
+        if let Some(&pgid) = pids.first() {
+            let idx = self.jobs.add(Job::new(pgid, pids, command));
+            let job = self.jobs.get(idx);
+            // like "exit", the notice is only for shells at a terminal
+            if self.job_control {
+                println!("[{}] {}", job.id, job.last_pid());
+            }
+            self.last_background = Some(job.last_pid());
+        }
+        self.last_status = 0;
+        Ok(Self::new_empty_output(0))
+    }
+
+    // runs an and-or list in a forked child in its own process group
+    fn fork_list(&mut self, node: Node) -> Result<i32, InterpretErr> {
+        let background = ProcessGroup {
+            pgid: 0,
+            foreground: false,
+        };
+        self.fork_compound(&[], None, None, Some(background), |crsh| crsh.and_or(node))
+    }
+
     fn execute_command(
-        tokens: &Vec<String>,
+        &mut self,
+        words: &[Node],
         redirects: &[Node],
-        stdin: Stdio,
-        stdout: Stdio,
-    ) -> Result<Option<Child>, InterpretErr> {
+        stdin: Option<OwnedFd>,
+        stdout: Option<OwnedFd>,
+        process_group: Option<ProcessGroup>,
+    ) -> Result<Stage, InterpretErr> {
+        self.substitution_status = None;
+        let mut assignments = vec![];
+        let mut command_words = words;
+        while let Some((assignment @ (Node::Assignment(..) | Node::AppendAssignment(..)), rest)) =
+            command_words.split_first()
+        {
+            assignments.push(self.expand_assignment(assignment)?);
+            command_words = rest;
+        }
+        // the assignments of "declare" and "local" are made after the builtin declared the
+        // variables, the ones of "export" before it exports them
+        let mut declared = vec![];
+        let command_words: Cow<[Node]> = if command_words.iter().any(Self::is_assignment) {
+            let words = command_words.iter().map(|word| match word {
+                Node::Assignment(name, _) | Node::AppendAssignment(name, _) => {
+                    declared.push(word);
+                    Node::Word(
+                        split_subscript(name)
+                            .map_or(name.as_str(), |(name, _)| name)
+                            .to_string(),
+                    )
+                }
+                word => word.clone(),
+            });
+            Cow::Owned(words.collect())
+        } else {
+            Cow::Borrowed(command_words)
+        };
+        let tokens = self.expand_words(&command_words)?;
         if tokens.is_empty() {
-            return Err(InterpretErr::RuntimeError("Empty command"));
+            // without a command the assignments set shell variables, and the status is
+            // the one of the last command substitution, as in "out=$(make)"
+            for assignment in assignments {
+                self.assign(assignment)?;
+            }
+            return Ok(Stage::Finished(self.substitution_status.unwrap_or(0)));
         }
+        // only plain variables end up in the environment of a command
+        let assignments: Vec<(String, String)> = assignments
+            .into_iter()
+            .filter_map(|assignment| match assignment.value {
+                AssignedValue::Scalar(value) if is_valid_name(&assignment.name) => {
+                    let value = match assignment.append {
+                        true => self.parameter(&assignment.name).unwrap_or_default() + &value,
+                        false => value,
+                    };
+                    Some((assignment.name, value))
+                }
+                _ => None,
+            })
+            .collect();
         let command = tokens[0].as_str();
         let args = &tokens[1..];
-        let mut cmd_stdin = stdin;
-        let mut cmd_stdout = stdout;
-        // TODO add piping for builtins
+        // functions come before builtins and commands in PATH
+        if let Some(body) = self.functions.get(command).cloned() {
+            let call = FunctionCall {
+                body: &body,
+                args,
+                assignments: &assignments,
+            };
+            return self.call_function(call, redirects, stdin, stdout, process_group);
+        }
+        // like functions, builtins run in the shell unless they're part of a pipeline or
+        // in the background
+        if BUILTINS.contains(&command) {
+            let run = |crsh: &mut Self| {
+                let code = crsh.builtin(command, args, &declared)?;
+                Ok(Self::new_empty_output(code))
+            };
+            let foreground = process_group.is_none_or(|group| group.foreground);
+            return if stdin.is_none() && stdout.is_none() && foreground {
+                self.compound(redirects, run)
+            } else {
+                self.fork_compound(redirects, stdin, stdout, process_group, run)
+                    .map(Stage::Forked)
+            };
+        }
+        let mut fds = FdTable::new(stdin, stdout);
         for redirect in redirects {
-            match redirect {
-                Node::RedirectRead(filename) => {
-                    let file = OpenOptions::new()
-                        .read(true)
-                        .open(filename)
-                        .map_err(|_| InterpretErr::RuntimeError("Failed opening file"))?;
-                    cmd_stdin = Stdio::from(file);
-                }
-                Node::RedirectWrite(filename) => {
-                    let file = OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .truncate(true)
-                        .open(filename)
-                        .map_err(|_| InterpretErr::RuntimeError("Failed opening file"))?;
-                    cmd_stdout = Stdio::from(file);
-                }
-                Node::RedirectAppend(filename) => {
-                    let file = OpenOptions::new()
-                        .append(true)
-                        .open(filename)
-                        .map_err(|_| InterpretErr::RuntimeError("Failed opening file"))?;
-                    cmd_stdout = Stdio::from(file);
-                }
-                _ => panic!("Unexpected node for redirect: {:?}", redirect),
-            }
+            self.redirect(&mut fds, redirect)?;
         }
+        self.general_command(command, args, &assignments, &fds, process_group)
+    }
+
+    // runs a builtin and returns its exit code
+    fn builtin(
+        &mut self,
+        command: &str,
+        args: &[String],
+        declared: &[&Node],
+    ) -> Result<i32, InterpretErr> {
         let res = match command {
-            "cd" => Self::cd_command(args),
-            "exit" => Self::exit_command(args),
-            _ => Self::general_command(command, args, cmd_stdin, cmd_stdout),
+            "cd" => self.cd_command(args),
+            "exit" => self.exit_command(args),
+            "export" => {
+                for assignment in declared {
+                    let assignment = self.expand_assignment(assignment)?;
+                    self.assign(assignment)?;
+                }
+                self.export_command(args)
+            }
+            "unset" => self.unset_command(args),
+            "jobs" => self.jobs_command(),
+            "fg" => return Self::stage_code(self.fg_command(args)),
+            "bg" => self.bg_command(args),
+            "wait" => return Self::stage_code(self.wait_command(args)),
+            "kill" => self.kill_command(args),
+            "shopt" => self.shopt_command(args),
+            "break" => self.loop_control_command("break", args),
+            "continue" => self.loop_control_command("continue", args),
+            "local" | "declare" => {
+                let res = match command {
+                    "local" => self.local_command(args),
+                    _ => self.declare_command(args),
+                };
+                if res.is_ok() {
+                    for assignment in declared {
+                        let assignment = self.expand_assignment(assignment)?;
+                        self.assign(assignment)?;
+                    }
+                }
+                res
+            }
+            "return" => self.return_command(args),
+            "let" => return Self::stage_code(self.let_command(args)),
+            _ => return Err(InterpretErr::RuntimeError("Unknown builtin")),
+        };
+        match res {
+            Ok(_) => Ok(0),
+            Err(InterpretErr::ExitStatusFailure(_)) => Ok(1),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn stage_code(stage: Result<Stage, InterpretErr>) -> Result<i32, InterpretErr> {
+        match stage? {
+            Stage::Finished(code) => Ok(code),
+            _ => Err(InterpretErr::RuntimeError("Unexpected stage of a builtin")),
+        }
+    }
+
+    fn is_assignment(node: &Node) -> bool {
+        matches!(node, Node::Assignment(..) | Node::AppendAssignment(..))
+    }
+
+    fn expand_assignment(&mut self, node: &Node) -> Result<Assignment, InterpretErr> {
+        let (name, value, append) = match node {
+            Node::Assignment(name, value) => (name, value, false),
+            Node::AppendAssignment(name, value) => (name, value, true),
+            _ => return Err(InterpretErr::RuntimeError("Unexpected node for assignment")),
+        };
+        let value = match value.as_ref() {
+            // elements without a subscript are split and globbed like arguments
+            Node::Array(elements) => {
+                let mut expanded = vec![];
+                for (subscript, word) in elements {
+                    match subscript {
+                        Some(_) => expanded.push((subscript.clone(), self.expand_value(word)?)),
+                        None => expanded.extend(
+                            self.expand_words(std::slice::from_ref(word))?
+                                .into_iter()
+                                .map(|value| (None, value)),
+                        ),
+                    }
+                }
+                AssignedValue::Array(expanded)
+            }
+            word => AssignedValue::Scalar(self.expand_value(word)?),
+        };
+        Ok(Assignment {
+            name: name.clone(),
+            append,
+            value,
+        })
+    }
+
+    // sets a variable, an element of an array as in "arr[1]=x", or a whole array as in
+    // "arr=(a b)". "+=" appends to the string or array.
+    fn assign(&mut self, assignment: Assignment) -> Result<(), InterpretErr> {
+        let Assignment {
+            name,
+            append,
+            value,
+        } = assignment;
+        match (split_subscript(&name), value) {
+            (None, AssignedValue::Scalar(value)) => {
+                let value = match append {
+                    true => self.variables.get(&name).unwrap_or_default().to_string() + &value,
+                    false => value,
+                };
+                self.variables.set(&name, value);
+            }
+            (Some((name, subscript)), AssignedValue::Scalar(value)) => {
+                self.assign_element(name, subscript, value, append)?;
+            }
+            (None, AssignedValue::Array(elements)) => {
+                if !append {
+                    self.variables.clear_array(&name);
+                }
+                let associative = self.variables.is_associative(&name);
+                let mut index = self.variables.next_index(&name);
+                for (subscript, value) in elements {
+                    match subscript {
+                        Some(subscript) => {
+                            if let Subscript::Index(assigned) =
+                                self.assign_element(&name, &subscript, value, false)?
+                            {
+                                index = assigned.checked_add(1);
+                            }
+                        }
+                        None if associative => {
+                            eprintln!(
+                                "crsh: {}: {}: must use subscript when assigning associative array",
+                                name, value
+                            );
+                            return Err(InterpretErr::ExitStatusFailure(""));
+                        }
+                        None => {
+                            let Some(next) = index else {
+                                eprintln!("crsh: {}: bad array subscript", name);
+                                return Err(InterpretErr::ExitStatusFailure(""));
+                            };
+                            self.variables
+                                .set_element(&name, Subscript::Index(next), value);
+                            index = next.checked_add(1);
+                        }
+                    }
+                }
+            }
+            (Some(_), AssignedValue::Array(_)) => {
+                eprintln!("crsh: {}: cannot assign list to array member", name);
+                return Err(InterpretErr::ExitStatusFailure(""));
+            }
+        }
+        Ok(())
+    }
+
+    // sets or appends to the element of an array, and returns the subscript it evaluated to
+    fn assign_element(
+        &mut self,
+        name: &str,
+        subscript: &str,
+        value: String,
+        append: bool,
+    ) -> Result<Subscript, InterpretErr> {
+        let resolved = self.subscript(name, subscript)?;
+        let value = match append {
+            true => {
+                let current = self.variables.get_element(name, &resolved);
+                current.unwrap_or_default().to_string() + &value
+            }
+            false => value,
+        };
+        if !self.variables.set_element(name, resolved.clone(), value) {
+            eprintln!("crsh: {}[{}]: bad array subscript", name, subscript);
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        Ok(resolved)
+    }
+
+    // the subscript of an associative array is a word, the one of an indexed array an
+    // arithmetic expression
+    fn subscript(&mut self, name: &str, subscript: &str) -> Result<Subscript, InterpretErr> {
+        if !self.variables.is_associative(name) {
+            return Ok(Subscript::Index(self.arithmetic(subscript)?));
+        }
+        let Ok(parts) = Scanner::arithmetic_parts(subscript) else {
+            eprintln!("crsh: {}: bad substitution", subscript);
+            return Err(InterpretErr::ExitStatusFailure(""));
         };
-        if let Err(InterpretErr::ExitStatusFailure(_)) = res {
-            Ok(None)
+        Ok(Subscript::Key(self.expand_parts(&parts)?))
+    }
+
+    // runs a compound command like "{ list; }" or a loop, or a function, in the shell itself,
+    // with its redirects applied to the shell's own descriptors while it runs
+    fn compound(
+        &mut self,
+        redirects: &[Node],
+        run: impl FnOnce(&mut Self) -> Result<Output, InterpretErr>,
+    ) -> Result<Stage, InterpretErr> {
+        let mut fds = FdTable::new(None, None);
+        for redirect in redirects {
+            self.redirect(&mut fds, redirect)?;
+        }
+        let saved = fds
+            .apply_to_shell()
+            .map_err(|_| InterpretErr::RuntimeError("Bad file descriptor"))?;
+        drop(fds);
+        let res = run(self);
+        // output of the shell itself must reach the redirected descriptors
+        let _ = stdout().flush();
+        drop(saved);
+        Ok(Stage::Finished(Self::exit_code(res?.status)))
+    }
+
+    // runs a function in the shell, unless it's part of a pipeline or in the background
+    fn call_function(
+        &mut self,
+        call: FunctionCall,
+        redirects: &[Node],
+        stdin: Option<OwnedFd>,
+        stdout: Option<OwnedFd>,
+        process_group: Option<ProcessGroup>,
+    ) -> Result<Stage, InterpretErr> {
+        let run = |crsh: &mut Self| crsh.run_function(call);
+        let foreground = process_group.is_none_or(|group| group.foreground);
+        if stdin.is_none() && stdout.is_none() && foreground {
+            self.compound(redirects, run)
         } else {
-            res
+            self.fork_compound(redirects, stdin, stdout, process_group, run)
+                .map(Stage::Forked)
+        }
+    }
+
+    // runs the body of a function with the arguments as positional parameters. Prefix
+    // assignments are exported local variables of the call, and loops outside of the
+    // function can't be left from inside it.
+    fn run_function(&mut self, call: FunctionCall) -> Result<Output, InterpretErr> {
+        if self.function_depth >= MAX_FUNCTION_DEPTH {
+            return Err(InterpretErr::RuntimeError(
+                "Maximum function nesting level exceeded",
+            ));
+        }
+        let mut positional = vec![self.positional[0].clone()];
+        positional.extend_from_slice(call.args);
+        let positional = std::mem::replace(&mut self.positional, positional);
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+        self.variables.push_scope();
+        for (name, value) in call.assignments {
+            self.variables.local(name, Some(value.clone()));
+            self.variables.export(name);
+        }
+        let res = self.and_or(Node::Pipeline(vec![call.body.clone()]));
+        self.variables.pop_scope();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        match res {
+            Err(InterpretErr::Return(code)) => Ok(Self::new_empty_output(code)),
+            res => res,
+        }
+    }
+
+    // runs the commands of a compound command, its redirects are already applied
+    fn run_compound(&mut self, node: &Node) -> Result<Output, InterpretErr> {
+        match node {
+            Node::Subshell(body, _) | Node::Group(body, _) => self.run(*body.clone()),
+            Node::If(branches, else_body, _) => {
+                for (condition, body) in branches {
+                    if self.run(condition.clone())?.status.success() {
+                        return self.run(body.clone());
+                    }
+                }
+                match else_body {
+                    Some(body) => self.run(*body.clone()),
+                    None => Ok(Self::new_empty_output(0)),
+                }
+            }
+            Node::While(condition, body, _) => self.run_loop(condition, body, false),
+            Node::Until(condition, body, _) => self.run_loop(condition, body, true),
+            Node::For(name, words, body, _) => {
+                let values = match words {
+                    Some(words) => self.expand_words(words)?,
+                    None => self.positional[1..].to_vec(),
+                };
+                self.with_loop(|crsh| {
+                    let mut output = Self::new_empty_output(0);
+                    for value in values {
+                        crsh.check_interrupt()?;
+                        crsh.variables.set(name, value);
+                        if !crsh.loop_body(body, &mut output)? {
+                            break;
+                        }
+                    }
+                    Ok(output)
+                })
+            }
+            Node::ArithFor(init, condition, step, body, _) => {
+                self.arithmetic(init)?;
+                self.with_loop(|crsh| {
+                    let mut output = Self::new_empty_output(0);
+                    loop {
+                        crsh.check_interrupt()?;
+                        // an empty condition is always true
+                        if !condition.trim().is_empty() && crsh.arithmetic(condition)? == 0 {
+                            return Ok(output);
+                        }
+                        if !crsh.loop_body(body, &mut output)? {
+                            return Ok(output);
+                        }
+                        crsh.arithmetic(step)?;
+                    }
+                })
+            }
+            Node::Case(word, items, _) => self.run_case(word, items),
+            // succeeds if the expression isn't 0
+            Node::Arithmetic(expression, _) => {
+                let value = self.arithmetic(expression)?;
+                Ok(Self::new_empty_output((value == 0) as i32))
+            }
+            Node::Select(name, words, body, _) => {
+                let values = match words {
+                    Some(words) => self.expand_words(words)?,
+                    None => self.positional[1..].to_vec(),
+                };
+                self.with_loop(|crsh| crsh.run_select(name, &values, body))
+            }
+            _ => Err(InterpretErr::RuntimeError("Unexpected compound command")),
+        }
+    }
+
+    // runs the body while the condition succeeds, or until it does. The status is the
+    // one of the last run of the body, 0 if it never ran.
+    fn run_loop(
+        &mut self,
+        condition: &Node,
+        body: &Node,
+        until: bool,
+    ) -> Result<Output, InterpretErr> {
+        self.with_loop(|crsh| {
+            let mut output = Self::new_empty_output(0);
+            loop {
+                crsh.check_interrupt()?;
+                if crsh.run(condition.clone())?.status.success() == until {
+                    return Ok(output);
+                }
+                if !crsh.loop_body(body, &mut output)? {
+                    return Ok(output);
+                }
+            }
+        })
+    }
+
+    // a loop of builtins has no child to get the SIGINT
+    fn check_interrupt(&self) -> Result<(), InterpretErr> {
+        if take_interrupt() {
+            return Err(InterpretErr::Interrupt("SIGINT Received"));
+        }
+        Ok(())
+    }
+
+    fn with_loop<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.loop_depth += 1;
+        let res = f(self);
+        self.loop_depth -= 1;
+        res
+    }
+
+    // runs one iteration of a loop body and returns whether the loop goes on. "break" and
+    // "continue" with a count above one are passed on to the enclosing loop.
+    fn loop_body(&mut self, body: &Node, output: &mut Output) -> Result<bool, InterpretErr> {
+        match self.run(body.clone()) {
+            Ok(res) => {
+                *output = res;
+                Ok(true)
+            }
+            Err(InterpretErr::Break(1)) => {
+                *output = Self::new_empty_output(0);
+                Ok(false)
+            }
+            Err(InterpretErr::Break(count)) => Err(InterpretErr::Break(count - 1)),
+            Err(InterpretErr::Continue(1)) => {
+                *output = Self::new_empty_output(0);
+                Ok(true)
+            }
+            Err(InterpretErr::Continue(count)) => Err(InterpretErr::Continue(count - 1)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // runs the body of the first item with a pattern matching the word. ";&" goes on with
+    // the next body without matching, ";;&" goes on matching the items after it.
+    fn run_case(&mut self, word: &Node, items: &[CaseItem]) -> Result<Output, InterpretErr> {
+        let word = self.expand_value(word)?;
+        let mut output = Self::new_empty_output(0);
+        let mut fall_through = false;
+        for item in items {
+            if !fall_through && !self.case_matches(&word, &item.patterns)? {
+                continue;
+            }
+            output = self.run(item.body.clone())?;
+            match item.terminator {
+                CaseTerminator::Break => break,
+                CaseTerminator::FallThrough => fall_through = true,
+                CaseTerminator::Continue => fall_through = false,
+            }
+        }
+        Ok(output)
+    }
+
+    fn case_matches(&mut self, word: &str, patterns: &[Node]) -> Result<bool, InterpretErr> {
+        for pattern in patterns {
+            if Pattern::new(&self.expand_pattern(pattern)?).matches(word) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // expands a word into a pattern, in which only unquoted glob characters and the
+    // results of unquoted expansions are special
+    fn expand_pattern(&mut self, word: &Node) -> Result<String, InterpretErr> {
+        match word {
+            Node::ExpandableWord(parts) => self.expand_pattern_parts(parts),
+            word => Ok(escape(&self.expand_value(word)?)),
+        }
+    }
+
+    fn expand_pattern_parts(&mut self, parts: &[WordPart]) -> Result<String, InterpretErr> {
+        let mut pattern = String::new();
+        for part in parts {
+            let value = match part {
+                WordPart::Glob(glob) => glob.clone(),
+                WordPart::Variable(_)
+                | WordPart::Parameter(..)
+                | WordPart::Arithmetic(_)
+                | WordPart::CommandSubstitution(_) => {
+                    self.expand_parts(std::slice::from_ref(part))?
+                }
+                part => escape(&self.expand_parts(std::slice::from_ref(part))?),
+            };
+            pattern.push_str(&value);
+        }
+        Ok(pattern)
+    }
+
+    // prints the words as a numbered menu and reads the choice after the $PS3 prompt, until
+    // the end of input or "break". $REPLY is the line read, and the variable the chosen word
+    // or empty if it isn't a number of the menu. An empty line shows the menu again.
+    fn run_select(
+        &mut self,
+        name: &str,
+        values: &[String],
+        body: &Node,
+    ) -> Result<Output, InterpretErr> {
+        let mut output = Self::new_empty_output(0);
+        let mut show_menu = true;
+        loop {
+            if show_menu {
+                for (idx, value) in values.iter().enumerate() {
+                    eprintln!("{}) {}", idx + 1, value);
+                }
+            }
+            eprint!("{}", self.variables.get("PS3").unwrap_or("#? "));
+            let Some(line) = self.read_line()? else {
+                // the menu ends with the input, on a line of its own
+                eprintln!();
+                return Ok(output);
+            };
+            show_menu = line.is_empty();
+            if show_menu {
+                continue;
+            }
+            let choice = line
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| values.get(number.checked_sub(1)?));
+            self.variables
+                .set(name, choice.cloned().unwrap_or_default());
+            self.variables.set("REPLY", line);
+            if !self.loop_body(body, &mut output)? {
+                return Ok(output);
+            }
+        }
+    }
+
+    // reads a line from stdin byte by byte, so nothing after it is taken from commands
+    // reading the same input. None at the end of input.
+    fn read_line(&self) -> Result<Option<String>, InterpretErr> {
+        let mut line = vec![];
+        loop {
+            let mut byte = 0u8;
+            let read = unsafe { libc::read(libc::STDIN_FILENO, (&raw mut byte).cast(), 1) };
+            match read {
+                -1 if IoError::last_os_error().kind() == ErrorKind::Interrupted => {
+                    self.check_interrupt()?
+                }
+                1 if byte != b'\n' => line.push(byte),
+                1 => break,
+                _ if line.is_empty() => return Ok(None),
+                _ => break,
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+
+    // expands the parameters and command substitutions in an arithmetic expression, and
+    // evaluates the result
+    fn arithmetic(&mut self, expression: &str) -> Result<i64, InterpretErr> {
+        let Ok(parts) = Scanner::arithmetic_parts(expression) else {
+            eprintln!("crsh: {}: bad substitution", expression.trim());
+            return Err(InterpretErr::ExitStatusFailure(""));
+        };
+        let expanded = self.expand_parts(&parts)?;
+        self.evaluate_arithmetic(&expanded)
+    }
+
+    // evaluates an expression that is already expanded, printing the error if it fails
+    fn evaluate_arithmetic(&mut self, expression: &str) -> Result<i64, InterpretErr> {
+        evaluate(expression, self).map_err(|err| {
+            eprintln!("crsh: {}: {}", expression.trim(), err.0);
+            InterpretErr::ExitStatusFailure("")
+        })
+    }
+
+    // runs a subshell, or a compound command or function that is part of a pipeline or in
+    // the background, in a forked copy of the shell. Changes to variables or the working
+    // directory in there don't affect the shell. Returns the pid of the copy.
+    fn fork_compound(
+        &mut self,
+        redirects: &[Node],
+        stdin: Option<OwnedFd>,
+        stdout: Option<OwnedFd>,
+        process_group: Option<ProcessGroup>,
+        run: impl FnOnce(&mut Self) -> Result<Output, InterpretErr>,
+    ) -> Result<i32, InterpretErr> {
+        // anything still buffered would otherwise be printed by both processes
+        let _ = std::io::stdout().flush();
+        match unsafe { libc::fork() } {
+            -1 => Err(InterpretErr::RuntimeError("Failed forking process")),
+            0 => {
+                self.setup_forked_shell(process_group);
+                let mut fds = FdTable::new(stdin, stdout);
+                let mut res = Ok(());
+                for redirect in redirects {
+                    res = res.and_then(|_| self.redirect(&mut fds, redirect));
+                }
+                match res.and_then(|_| {
+                    fds.apply_to_shell()
+                        .map_err(|_| InterpretErr::RuntimeError("Bad file descriptor"))
+                }) {
+                    // the copy exits without putting the descriptors back
+                    Ok(saved) => std::mem::forget(saved),
+                    Err(err) => unsafe { libc::_exit(Self::child_exit_code(Err(err))) },
+                }
+                drop(fds);
+                release_shell_fds();
+                let code = Self::child_exit_code(run(self));
+                let _ = std::io::stdout().flush();
+                unsafe { libc::_exit(code) }
+            }
+            pid => {
+                // also set in the parent, so the group exists as soon as fork returns
+                if let Some(group) = process_group {
+                    let pgid = if group.pgid == 0 { pid } else { group.pgid };
+                    unsafe { libc::setpgid(pid, pgid) };
+                }
+                Ok(pid)
+            }
+        }
+    }
+
+    // turns the forked copy of the shell into a child like any other command
+    fn setup_forked_shell(&mut self, process_group: Option<ProcessGroup>) {
+        // copies outside of a process group, like command substitutions, stay part of the
+        // shell's job and keep ignoring ctrl+Z
+        if let Some(group) = process_group {
+            unsafe { libc::setpgid(0, group.pgid) };
+            reset_child_signals(group.foreground);
+        }
+        unsafe {
+            // ctrl+c ends the whole copy, not just the command running in it
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            // the Rust runtime ignores SIGPIPE, so writing to a closed pipe would panic
+            libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        }
+        self.job_control = false;
+        self.jobs = Jobs::default();
+    }
+
+    fn redirect(&mut self, fds: &mut FdTable, redirect: &Node) -> Result<(), InterpretErr> {
+        let mut options = OpenOptions::new();
+        let (fd, target, options) = match redirect {
+            Node::RedirectRead(fd, target) => (fd, target, options.read(true)),
+            Node::RedirectWrite(fd, target) => {
+                (fd, target, options.write(true).create(true).truncate(true))
+            }
+            Node::RedirectAppend(fd, target) => (fd, target, options.append(true).create(true)),
+            Node::RedirectReadWrite(fd, target) => {
+                (fd, target, options.read(true).write(true).create(true))
+            }
+            Node::RedirectDuplicate(fd, target) => {
+                let source = self.expand_value(target)?;
+                if source == "-" {
+                    fds.close(*fd);
+                    return Ok(());
+                }
+                let res = match source.parse::<i32>() {
+                    Ok(number) => fds
+                        .duplicate(*fd, number)
+                        .map_err(|err| error_message(&err)),
+                    Err(_) => Err("ambiguous redirect".to_string()),
+                };
+                // like a file that can't be opened, this only fails the command
+                return res.map_err(|message| {
+                    eprintln!("crsh: {}: {}", source, message);
+                    InterpretErr::ExitStatusFailure("")
+                });
+            }
+            Node::RedirectHereDoc(fd, _, body) => {
+                return Self::here_document(fds, *fd, self.expand_value(body)?)
+            }
+            Node::RedirectHereString(fd, word) => {
+                let content = self.expand_value(word)? + "\n";
+                return Self::here_document(fds, *fd, content);
+            }
+            _ => return Err(InterpretErr::RuntimeError("Unexpected node for redirect")),
+        };
+        let path = self.expand_value(target)?;
+        let file = options.open(&path).map_err(|err| {
+            eprintln!("crsh: {}: {}", path, error_message(&err));
+            InterpretErr::ExitStatusFailure("")
+        })?;
+        fds.open(*fd, file);
+        Ok(())
+    }
+
+    // feeds the content to fd through a pipe. It is written from a thread, as a body
+    // that doesn't fit into the pipe would block until the command reads it.
+    fn here_document(fds: &mut FdTable, fd: i32, content: String) -> Result<(), InterpretErr> {
+        let (reader, mut writer) =
+            pipe().map_err(|_| InterpretErr::RuntimeError("Failed creating pipe"))?;
+        thread::spawn(move || writer.write_all(content.as_bytes()));
+        fds.open(fd, reader);
+        Ok(())
+    }
+
+    fn expand_words(&mut self, words: &[Node]) -> Result<Vec<String>, InterpretErr> {
+        let mut expanded = vec![];
+        for word in words {
+            match word {
+                Node::Word(string) => expanded.push(string.clone()),
+                Node::ExpandableWord(parts) => {
+                    for field in self.expand_fields(parts)? {
+                        expanded.extend(self.pathname_expansion(field)?);
+                    }
+                }
+                _ => return Err(InterpretErr::RuntimeError("Unexpected node for word")),
+            }
+        }
+        Ok(expanded)
+    }
+
+    // replaces a field with unquoted glob characters by the paths it matches. Without matches
+    // it stays as it is, unless nullglob or failglob is set.
+    fn pathname_expansion(&self, field: Field) -> Result<Vec<String>, InterpretErr> {
+        let Some(pattern) = field.pattern else {
+            return Ok(vec![field.text]);
+        };
+        let paths = glob(&pattern, self.glob_options);
+        if !paths.is_empty() {
+            Ok(paths)
+        } else if self.glob_options.failglob {
+            eprintln!("crsh: no match: {}", field.text);
+            Err(InterpretErr::ExitStatusFailure(""))
+        } else if self.glob_options.nullglob {
+            Ok(vec![])
+        } else {
+            Ok(vec![field.text])
+        }
+    }
+
+    // expands a single word without splitting it or dropping it when empty, as in "NAME=$EMPTY"
+    fn expand_value(&mut self, word: &Node) -> Result<String, InterpretErr> {
+        match word {
+            Node::Word(string) => Ok(string.clone()),
+            Node::ExpandableWord(parts) => self.expand_parts(parts),
+            _ => Err(InterpretErr::RuntimeError("Unexpected node for word")),
+        }
+    }
+
+    // looks up special parameters like "$?" before shell variables
+    fn parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "#" => Some((self.positional.len() - 1).to_string()),
+            "@" | "*" => Some(self.positional[1..].join(" ")),
+            _ if name.chars().all(|chr| chr.is_ascii_digit()) => {
+                self.positional.get(name.parse::<usize>().ok()?).cloned()
+            }
+            _ => self.variables.get(name).map(str::to_string),
+        }
+    }
+
+    // the value of a parameter, which can also be an element of an array as in "${arr[1]}".
+    // "${arr[@]}" joins the elements with spaces, and "${arr[*]}" with the first character
+    // of IFS.
+    fn lookup(&mut self, name: &str) -> Result<Option<String>, InterpretErr> {
+        let Some((array, subscript)) = split_subscript(name) else {
+            return Ok(self.parameter(name));
+        };
+        if subscript == "@" || subscript == "*" {
+            let values = self.variables.values(array);
+            if values.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(self.join(&values, subscript == "*")));
+        }
+        let subscript = self.subscript(array, subscript)?;
+        let value = self.variables.get_element(array, &subscript);
+        Ok(value.map(str::to_string))
+    }
+
+    fn join(&self, values: &[String], ifs: bool) -> String {
+        let separator = match ifs {
+            true => self
+                .ifs()
+                .chars()
+                .next()
+                .map(String::from)
+                .unwrap_or_default(),
+            false => " ".to_string(),
+        };
+        values.join(&separator)
+    }
+
+    // the elements of "${arr[@]}" or "${arr[*]}", or its keys with "${!arr[@]}". Pattern
+    // and case operators apply to each element. Returns whether it's "*" as well.
+    fn list_expansion(
+        &mut self,
+        name: &str,
+        operator: Option<&ParameterOperator>,
+    ) -> Result<Option<(Vec<String>, bool)>, InterpretErr> {
+        let Some((array, subscript @ ("@" | "*"))) = split_subscript(name) else {
+            return Ok(None);
+        };
+        let values = match operator {
+            None => self.variables.values(array),
+            Some(ParameterOperator::Keys) => self.variables.keys(array),
+            Some(
+                operator @ (ParameterOperator::RemovePrefix(..)
+                | ParameterOperator::RemoveSuffix(..)
+                | ParameterOperator::Replace(..)
+                | ParameterOperator::Case(..)),
+            ) => {
+                let mut values = vec![];
+                for value in self.variables.values(array) {
+                    values.push(self.modify(value, operator)?);
+                }
+                values
+            }
+            Some(_) => return Ok(None),
+        };
+        Ok(Some((values, subscript == "*")))
+    }
+
+    // characters that split the results of unquoted expansions
+    fn ifs(&self) -> &str {
+        self.variables.get("IFS").unwrap_or(" \t\n")
+    }
+
+    // expands a word into the arguments it turns into. Unquoted expansions are split into
+    // fields and give none if they are empty, "$@" gives one per positional parameter.
+    fn expand_fields(&mut self, parts: &[WordPart]) -> Result<Vec<Field>, InterpretErr> {
+        let mut fields = Fields::default();
+        self.expand_into_fields(parts, false, &mut fields)?;
+        Ok(fields.finish())
+    }
+
+    fn expand_into_fields(
+        &mut self,
+        parts: &[WordPart],
+        quoted: bool,
+        fields: &mut Fields,
+    ) -> Result<(), InterpretErr> {
+        for part in parts {
+            // like "$@", the elements of "${arr[@]}" are separate arguments
+            let list = match part {
+                WordPart::Variable(name) => self.list_expansion(name, None)?,
+                WordPart::Parameter(name, operator) => self.list_expansion(name, Some(operator))?,
+                _ => None,
+            };
+            match (part, list) {
+                (_, Some((values, true))) if quoted => fields.push_str(&self.join(&values, true)),
+                (_, Some((values, _))) => self.push_list(&values, quoted, fields),
+                (WordPart::Literal(literal), _) => fields.push_str(literal),
+                (WordPart::Glob(glob), _) => fields.push_glob(glob),
+                (WordPart::Tilde(name), _) => fields.push_str(&self.tilde_expansion(name)),
+                (WordPart::Variable(name), _) if name == "@" || (name == "*" && !quoted) => {
+                    let args = self.positional[1..].to_vec();
+                    self.push_list(&args, quoted, fields);
+                }
+                (WordPart::DoubleQuoted(inner), _) => {
+                    // quotes keep an empty argument, except for a "$@" or "${arr[@]}"
+                    // without elements
+                    let list = match inner.as_slice() {
+                        [WordPart::Variable(name) | WordPart::Parameter(name, _)] => {
+                            name == "@" || name.ends_with("[@]")
+                        }
+                        _ => false,
+                    };
+                    if !list {
+                        fields.keep_empty();
+                    }
+                    self.expand_into_fields(inner, true, fields)?;
+                }
+                (part, None) => {
+                    let value = self.expand_parts(std::slice::from_ref(part))?;
+                    if quoted {
+                        fields.push_str(&value);
+                    } else {
+                        fields.push_split(&value, self.ifs());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // adds each value as its own field, split further if it's unquoted
+    fn push_list(&self, values: &[String], quoted: bool, fields: &mut Fields) {
+        for (idx, value) in values.iter().enumerate() {
+            if idx > 0 {
+                fields.end_field();
+            }
+            if quoted {
+                fields.keep_empty();
+                fields.push_str(value);
+            } else {
+                fields.push_split(value, self.ifs());
+            }
+        }
+    }
+
+    fn expand_parts(&mut self, parts: &[WordPart]) -> Result<String, InterpretErr> {
+        let mut string = String::new();
+        for part in parts {
+            match part {
+                WordPart::Literal(literal) | WordPart::Glob(literal) => string.push_str(literal),
+                WordPart::Variable(name) => {
+                    string.push_str(&self.lookup(name)?.unwrap_or_default())
+                }
+                WordPart::DoubleQuoted(inner) => string.push_str(&self.expand_parts(inner)?),
+                WordPart::Tilde(name) => string.push_str(&self.tilde_expansion(name)),
+                // the parser already expanded braces in words where they apply
+                WordPart::Brace(_) => string.push_str(&part.to_string()),
+                WordPart::CommandSubstitution(node) => {
+                    string.push_str(&self.command_substitution(node)?)
+                }
+                WordPart::Arithmetic(expression) => {
+                    string.push_str(&self.arithmetic(expression)?.to_string())
+                }
+                WordPart::Parameter(name, operator) => {
+                    string.push_str(&self.parameter_expansion(name, operator)?)
+                }
+            }
+        }
+        Ok(string)
+    }
+
+    // applies the operator of "${name<operator>...}" to the value of the parameter
+    fn parameter_expansion(
+        &mut self,
+        name: &str,
+        operator: &ParameterOperator,
+    ) -> Result<String, InterpretErr> {
+        if let Some((values, ifs)) = self.list_expansion(name, Some(operator))? {
+            return Ok(self.join(&values, ifs));
+        }
+        let value = self.lookup(name)?;
+        // the test operators check whether the parameter is unset, or empty with a ":"
+        let missing = |test_empty: bool| {
+            value
+                .as_ref()
+                .is_none_or(|value| test_empty && value.is_empty())
+        };
+        let value = match operator {
+            ParameterOperator::Default(test_empty, word) if missing(*test_empty) => {
+                self.expand_parts(word)?
+            }
+            ParameterOperator::Assign(test_empty, word) if missing(*test_empty) => {
+                let word = self.expand_parts(word)?;
+                if !is_valid_name(name) {
+                    eprintln!("crsh: ${}: cannot assign in this way", name);
+                    return Err(InterpretErr::ExitStatusFailure(""));
+                }
+                self.variables.set(name, word.clone());
+                word
+            }
+            ParameterOperator::Error(test_empty, word) if missing(*test_empty) => {
+                let message = match self.expand_parts(word)? {
+                    message if message.is_empty() => "parameter null or not set".to_string(),
+                    message => message,
+                };
+                eprintln!("crsh: {}: {}", name, message);
+                // scripts stop here, as in "${1:?usage: script file}"
+                if !self.job_control {
+                    let _ = stdout().flush();
+                    exit(1);
+                }
+                return Err(InterpretErr::ExitStatusFailure(""));
+            }
+            ParameterOperator::Alternative(test_empty, word) => {
+                if missing(*test_empty) {
+                    String::new()
+                } else {
+                    self.expand_parts(word)?
+                }
+            }
+            ParameterOperator::Length if name == "@" || name == "*" => {
+                (self.positional.len() - 1).to_string()
+            }
+            ParameterOperator::Length => match split_subscript(name) {
+                Some((array, "@" | "*")) => self.variables.values(array).len().to_string(),
+                _ => value.unwrap_or_default().chars().count().to_string(),
+            },
+            ParameterOperator::RemovePrefix(..)
+            | ParameterOperator::RemoveSuffix(..)
+            | ParameterOperator::Replace(..)
+            | ParameterOperator::Case(..) => self.modify(value.unwrap_or_default(), operator)?,
+            ParameterOperator::Substring(offset, length) => {
+                let offset = self.arithmetic(offset)?;
+                let length = match length {
+                    Some(length) => Some(self.arithmetic(length)?),
+                    None => None,
+                };
+                let Some(substring) = substring(&value.unwrap_or_default(), offset, length) else {
+                    eprintln!("crsh: {}: substring expression < 0", name);
+                    return Err(InterpretErr::ExitStatusFailure(""));
+                };
+                substring
+            }
+            // the test operators use the value if it's there
+            _ => value.unwrap_or_default(),
+        };
+        Ok(value)
+    }
+
+    // applies an operator that removes, replaces or changes the case of text in a value
+    fn modify(
+        &mut self,
+        value: String,
+        operator: &ParameterOperator,
+    ) -> Result<String, InterpretErr> {
+        let value = match operator {
+            ParameterOperator::RemovePrefix(longest, pattern) => {
+                let pattern = Pattern::new(&self.expand_pattern_parts(pattern)?);
+                remove_prefix(&value, &pattern, *longest)
+            }
+            ParameterOperator::RemoveSuffix(longest, pattern) => {
+                let pattern = Pattern::new(&self.expand_pattern_parts(pattern)?);
+                remove_suffix(&value, &pattern, *longest)
+            }
+            ParameterOperator::Replace(mode, pattern, replacement) => {
+                let pattern = Pattern::new(&self.expand_pattern_parts(pattern)?);
+                let replacement = self.expand_parts(replacement)?;
+                replace(&value, &pattern, &replacement, *mode)
+            }
+            ParameterOperator::Case(upper, all) => change_case(&value, *upper, *all),
+            _ => value,
+        };
+        Ok(value)
+    }
+
+    // "~" is $HOME, "~+" and "~-" are $PWD and $OLDPWD, and "~user" the home of that user.
+    // It stays as it is if there is nothing to replace it with.
+    fn tilde_expansion(&self, name: &str) -> String {
+        let expanded = match name {
+            "" => self.variables.get("HOME").map(str::to_string),
+            "+" => self.variables.get("PWD").map(str::to_string),
+            "-" => self.variables.get("OLDPWD").map(str::to_string),
+            user => Self::user_home(user),
+        };
+        expanded.unwrap_or_else(|| format!("~{}", name))
+    }
+
+    // home directory of a user from the passwd database
+    fn user_home(user: &str) -> Option<String> {
+        let user = CString::new(user).ok()?;
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buffer: Vec<libc::c_char> = vec![0; 4096];
+        let mut result = std::ptr::null_mut();
+        let res = unsafe {
+            libc::getpwnam_r(
+                user.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if res != 0 || result.is_null() {
+            return None;
+        }
+        let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+        Some(home.to_string_lossy().into_owned())
+    }
+
+    // runs the command in a forked copy of the shell and returns what it printed, without
+    // trailing newlines. Builtins run in the copy too, so "$(cd dir)" changes nothing.
+    fn command_substitution(&mut self, node: &Node) -> Result<String, InterpretErr> {
+        let (mut reader, writer) =
+            pipe().map_err(|_| InterpretErr::RuntimeError("Failed creating pipe"))?;
+        let run = |crsh: &mut Self| crsh.run(node.clone());
+        let pid = self.fork_compound(&[], None, Some(writer.into()), None, run)?;
+        let mut output = vec![];
+        let _ = reader.read_to_end(&mut output);
+        let mut status = 0;
+        while unsafe { libc::waitpid(pid, &mut status, 0) } == -1
+            && IoError::last_os_error().kind() == ErrorKind::Interrupted
+        {}
+        let code = wait_status_code(status);
+        self.last_status = code;
+        self.substitution_status = Some(code);
+        // ctrl+c reaches the shell as well, which shouldn't pass it on to the next job
+        if take_interrupt() || code == 128 + libc::SIGINT {
+            return Err(InterpretErr::Interrupt("SIGINT Received"));
+        }
+        let output = String::from_utf8_lossy(&output);
+        Ok(output.trim_end_matches('\n').to_string())
+    }
+
+    // exit code of a forked copy of the shell. "break" in there only leaves the loops
+    // inside the copy, as in "for f in *; do (break); done".
+    fn child_exit_code(res: Result<Output, InterpretErr>) -> i32 {
+        match res {
+            Ok(output) => Self::exit_code(output.status),
+            Err(InterpretErr::Interrupt(_)) => 128 + libc::SIGINT,
+            Err(InterpretErr::Break(_) | InterpretErr::Continue(_)) => 0,
+            Err(InterpretErr::Return(code)) => code,
+            Err(InterpretErr::ExitStatusFailure(_)) => 1,
+            Err(err) => {
+                eprintln!("Execution error: {}", err);
+                1
+            }
+        }
+    }
+
+    fn exit_code(status: ExitStatus) -> i32 {
+        match status.code() {
+            Some(code) => code,
+            None => 128 + status.signal().unwrap_or(0),
         }
     }
 
     fn new_empty_output(exit_code: i32) -> Output {
         Output {
-            status: ExitStatusExt::from_raw(exit_code),
+            // from_raw takes a wait status, which keeps the exit code in the second byte
+            status: ExitStatusExt::from_raw((exit_code & 0xff) << 8),
             stdout: vec![],
             stderr: vec![],
         }
     }
 
-    fn cd_command(args: &[String]) -> Result<Option<Child>, InterpretErr> {
-        if args.len() != 1 {
-            println!("Too many directories");
+    // changes to $HOME without an argument and to $OLDPWD with "-", and updates $PWD and $OLDPWD
+    fn cd_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        if args.len() > 1 {
+            eprintln!("Too many directories");
             return Err(InterpretErr::ExitStatusFailure(""));
         }
-        let new_dir = &args[0];
+        let (name, new_dir) = match args.first().map(String::as_str) {
+            None => ("HOME", self.variables.get("HOME")),
+            Some("-") => ("OLDPWD", self.variables.get("OLDPWD")),
+            Some(dir) => ("", Some(dir)),
+        };
+        let Some(new_dir) = new_dir else {
+            eprintln!("cd: {} not set", name);
+            return Err(InterpretErr::ExitStatusFailure(""));
+        };
+        let new_dir = new_dir.to_string();
         let absolute_new_dir = Path::new(&new_dir);
-        match set_current_dir(absolute_new_dir) {
-            Ok(_) => Ok(None),
-            Err(_) => {
-                println!("Failed changing directory");
-                Err(InterpretErr::ExitStatusFailure(""))
-            }
+        if set_current_dir(absolute_new_dir).is_err() {
+            eprintln!("Failed changing directory");
+            return Err(InterpretErr::ExitStatusFailure(""));
         }
+        if let Some(old_dir) = self.variables.get("PWD") {
+            self.variables.set("OLDPWD", old_dir.to_string());
+        }
+        self.update_pwd();
+        if name == "OLDPWD" {
+            println!("{}", new_dir);
+        }
+        Ok(None)
     }
 
-    fn exit_command(args: &[String]) -> Result<Option<Child>, InterpretErr> {
-        let mut exit_code = 0;
+    fn update_pwd(&mut self) {
+        if let Ok(dir) = current_dir() {
+            self.variables
+                .set("PWD", dir.to_string_lossy().into_owned());
+        }
+    }
+
+    fn exit_command(&self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let mut exit_code = self.last_status;
         if args.len() > 1 {
-            println!("Too many arguments");
+            eprintln!("Too many arguments");
             return Err(InterpretErr::ExitStatusFailure(""));
         }
         if args.len() == 1 {
             match i32::from_str(&args[0]) {
                 Ok(code) => exit_code = code,
                 Err(_) => {
-                    println!("Didn't pass numeric argument");
+                    eprintln!("Didn't pass numeric argument");
                     return Err(InterpretErr::ExitStatusFailure(""));
                 }
             }
         }
-        println!("exit");
+        // only shells at a terminal say goodbye, scripts exit quietly
+        if self.job_control {
+            println!("exit");
+        }
         exit(exit_code);
     }
 
+    fn export_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        if args.is_empty() {
+            for (name, value) in self.variables.exported() {
+                println!("export {}={:?}", name, value);
+            }
+            return Ok(None);
+        }
+        let mut failed = false;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_valid_name(name) {
+                eprintln!("export: {:?} is not a valid name", name);
+                failed = true;
+                continue;
+            }
+            if let Some(value) = value {
+                self.variables.set(name, value.to_string());
+            }
+            self.variables.export(name);
+        }
+        if failed {
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        Ok(None)
+    }
+
+    // "unset -f" removes functions and "-v" variables. Without a flag a name is a variable,
+    // or a function if there is no such variable. "unset 'arr[1]'" removes an element.
+    fn unset_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let (flag, names) = match args.split_first() {
+            Some((flag, names)) if flag == "-f" || flag == "-v" => (Some(flag.as_str()), names),
+            _ => (None, args),
+        };
+        for name in names {
+            if let (Some((array, subscript)), false) = (split_subscript(name), flag == Some("-f")) {
+                if subscript == "@" || subscript == "*" {
+                    self.variables.unset(array);
+                } else {
+                    let subscript = self.subscript(array, subscript)?;
+                    self.variables.unset_element(array, &subscript);
+                }
+                continue;
+            }
+            let function = match flag {
+                Some(flag) => flag == "-f",
+                None => self.variables.get(name).is_none(),
+            };
+            if function {
+                self.functions.remove(name);
+            } else {
+                self.variables.unset(name);
+            }
+        }
+        Ok(None)
+    }
+
+    fn jobs_command(&mut self) -> Result<Option<Child>, InterpretErr> {
+        self.jobs.poll();
+        for job in self.jobs.iter() {
+            println!("{}", job);
+        }
+        // finished jobs were just reported, so they are dropped like in report_jobs
+        self.jobs.remove_finished();
+        Ok(None)
+    }
+
+    // finds the job named by the only argument, or the most recent job without one
+    fn job_argument(&self, builtin: &str, args: &[String]) -> Result<usize, InterpretErr> {
+        if args.len() > 1 {
+            eprintln!("{}: Too many arguments", builtin);
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        let spec = args.first().map_or("%%", String::as_str);
+        match self.jobs.find(spec) {
+            Some(idx) => Ok(idx),
+            None => {
+                eprintln!("{}: {}: no such job", builtin, spec);
+                Err(InterpretErr::ExitStatusFailure(""))
+            }
+        }
+    }
+
+    fn fg_command(&mut self, args: &[String]) -> Result<Stage, InterpretErr> {
+        let idx = match self.job_argument("fg", args) {
+            Ok(idx) => idx,
+            Err(_) => return Ok(Stage::Finished(1)),
+        };
+        let job = self.jobs.get_mut(idx);
+        println!("{}", job.command);
+        if self.job_control {
+            give_terminal(job.pgid);
+        }
+        job.resume();
+        let code = self.wait_handle_interrupts(idx)?;
+        Ok(Stage::Finished(code))
+    }
+
+    fn bg_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let idx = self.job_argument("bg", args)?;
+        let job = self.jobs.get_mut(idx);
+        job.resume();
+        println!("[{}] {} &", job.id, job.command);
+        Ok(None)
+    }
+
+    fn wait_command(&mut self, args: &[String]) -> Result<Stage, InterpretErr> {
+        if args.is_empty() {
+            while !self.jobs.is_empty() {
+                self.wait_job(0)?;
+                self.jobs.remove(0);
+            }
+            return Ok(Stage::Finished(0));
+        }
+        let mut code = 0;
+        for spec in args {
+            code = match self.jobs.find(spec) {
+                Some(idx) => {
+                    let code = self.wait_job(idx)?;
+                    self.jobs.remove(idx);
+                    code
+                }
+                None => {
+                    eprintln!("wait: {}: no such job", spec);
+                    127
+                }
+            };
+        }
+        Ok(Stage::Finished(code))
+    }
+
+    fn kill_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let (signal, targets) = match args {
+            [flag, name, targets @ ..] if flag == "-s" => (signal_number(name), targets),
+            [flag, targets @ ..] if flag.starts_with('-') => (signal_number(&flag[1..]), targets),
+            targets => (Some(libc::SIGTERM), targets),
+        };
+        let Some(signal) = signal else {
+            eprintln!("kill: Unknown signal");
+            return Err(InterpretErr::ExitStatusFailure(""));
+        };
+        if targets.is_empty() {
+            eprintln!("kill: Expected a job or pid");
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        let mut failed = false;
+        for target in targets {
+            let sent = if target.starts_with('%') {
+                match self.jobs.find(target) {
+                    // stopped jobs have to be continued to act on the signal
+                    Some(idx) if self.jobs.get(idx).state() == JobState::Stopped => {
+                        self.jobs.get(idx).signal(signal) && self.jobs.get_mut(idx).resume()
+                    }
+                    Some(idx) => self.jobs.get(idx).signal(signal),
+                    None => false,
+                }
+            } else {
+                match target.parse::<i32>() {
+                    Ok(pid) => unsafe { libc::kill(pid, signal) == 0 },
+                    Err(_) => false,
+                }
+            };
+            if !sent {
+                eprintln!("kill: {}: Failed sending signal", target);
+                failed = true;
+            }
+        }
+        if failed {
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        Ok(None)
+    }
+
+    // "shopt -s name" sets an option, "-u" unsets it, and without a flag the options are listed
+    fn shopt_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let (value, names) = match args.split_first() {
+            Some((flag, names)) if flag == "-s" => (Some(true), names),
+            Some((flag, names)) if flag == "-u" => (Some(false), names),
+            _ => (None, args),
+        };
+        if names.is_empty() {
+            for name in GlobOptions::names() {
+                let enabled = *self.glob_options.option_mut(name).unwrap();
+                if value.is_none_or(|value| value == enabled) {
+                    println!("{:<16}{}", name, if enabled { "on" } else { "off" });
+                }
+            }
+            return Ok(None);
+        }
+        let mut failed = false;
+        for name in names {
+            match (self.glob_options.option_mut(name), value) {
+                (Some(option), Some(value)) => *option = value,
+                (Some(option), None) => {
+                    println!("{:<16}{}", name, if *option { "on" } else { "off" })
+                }
+                (None, _) => {
+                    eprintln!("shopt: {}: invalid shell option name", name);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        Ok(None)
+    }
+
+    // "local name[=value]..." makes variables local to the function that runs it
+    fn local_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let mut failed = false;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !is_valid_name(name) {
+                eprintln!("local: {:?} is not a valid name", name);
+                failed = true;
+                continue;
+            }
+            if !self.variables.local(name, value) {
+                eprintln!("local: can only be used in a function");
+                return Err(InterpretErr::ExitStatusFailure(""));
+            }
+        }
+        if failed {
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        Ok(None)
+    }
+
+    // "declare [-a|-A] name[=value]..." declares indexed or associative arrays. In a
+    // function the variables are local to it.
+    fn declare_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        let mut associative = None;
+        let mut names = args;
+        while let Some((flag, rest)) = names.split_first() {
+            match flag.as_str() {
+                "-a" => associative = Some(false),
+                "-A" => associative = Some(true),
+                _ => break,
+            }
+            names = rest;
+        }
+        let mut failed = false;
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !is_valid_name(name) {
+                eprintln!("declare: {:?} is not a valid name", name);
+                failed = true;
+                continue;
+            }
+            // outside of functions this does nothing
+            self.variables.local(name, None);
+            if let Some(associative) = associative {
+                if let Err(msg) = self.variables.declare_array(name, associative) {
+                    eprintln!("crsh: declare: {}: {}", name, msg);
+                    failed = true;
+                    continue;
+                }
+            }
+            if let Some(value) = value {
+                self.variables.set(name, value);
+            }
+        }
+        if failed {
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        Ok(None)
+    }
+
+    // "return N" leaves the function with status N, or the status of the last command
+    fn return_command(&mut self, args: &[String]) -> Result<Option<Child>, InterpretErr> {
+        if self.function_depth == 0 {
+            eprintln!("return: can only `return' from a function");
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        let code = match args {
+            [] => self.last_status,
+            [code] => match code.parse::<i32>() {
+                Ok(code) => code & 0xff,
+                Err(_) => {
+                    eprintln!("return: {}: numeric argument required", code);
+                    2
+                }
+            },
+            _ => {
+                eprintln!("return: Too many arguments");
+                return Err(InterpretErr::ExitStatusFailure(""));
+            }
+        };
+        Err(InterpretErr::Return(code))
+    }
+
+    // evaluates each argument as an arithmetic expression, and succeeds if the last one
+    // isn't 0
+    fn let_command(&mut self, args: &[String]) -> Result<Stage, InterpretErr> {
+        if args.is_empty() {
+            eprintln!("let: Expected an expression");
+            return Ok(Stage::Finished(1));
+        }
+        let mut value = 0;
+        for arg in args {
+            value = match self.evaluate_arithmetic(arg) {
+                Ok(value) => value,
+                Err(_) => return Ok(Stage::Finished(1)),
+            };
+        }
+        Ok(Stage::Finished((value == 0) as i32))
+    }
+
+    // "break N" leaves the N innermost loops, "continue N" goes on with the next iteration
+    // of the Nth one
+    fn loop_control_command(
+        &mut self,
+        builtin: &str,
+        args: &[String],
+    ) -> Result<Option<Child>, InterpretErr> {
+        if args.len() > 1 {
+            eprintln!("{}: Too many arguments", builtin);
+            return Err(InterpretErr::ExitStatusFailure(""));
+        }
+        let count = match args.first().map(|arg| arg.parse::<usize>()) {
+            None => 1,
+            Some(Ok(count)) if count > 0 => count,
+            Some(_) => {
+                eprintln!("{}: {}: loop count out of range", builtin, args[0]);
+                return Err(InterpretErr::ExitStatusFailure(""));
+            }
+        };
+        if self.loop_depth == 0 {
+            eprintln!(
+                "{}: only meaningful in a `for', `while', `until', or `select' loop",
+                builtin
+            );
+            return Ok(None);
+        }
+        // a count past the outermost loop leaves all of them
+        let count = count.min(self.loop_depth);
+        if builtin == "break" {
+            Err(InterpretErr::Break(count))
+        } else {
+            Err(InterpretErr::Continue(count))
+        }
+    }
+
     fn general_command(
+        &self,
         command: &str,
         args: &[String],
-        stdin: Stdio,
-        stdout: Stdio,
-    ) -> Result<Option<Child>, InterpretErr> {
-        let child_result = Command::new(command)
+        assignments: &[(String, String)],
+        fds: &FdTable,
+        process_group: Option<ProcessGroup>,
+    ) -> Result<Stage, InterpretErr> {
+        let mut child_command = Command::new(command);
+        // prefix assignments only end up in the environment of the child
+        child_command
             .args(args)
-            .stdin(stdin)
-            .stdout(stdout)
-            .spawn();
+            .env_clear()
+            .envs(self.variables.exported())
+            .envs(assignments.iter().map(|(name, value)| (name, value)));
+        if let Err(err) = fds.apply(&mut child_command) {
+            eprintln!("{}: {}", command, err);
+            return Ok(Stage::Finished(1));
+        }
+        let take_terminal = process_group.is_some_and(|group| group.foreground);
+        if let Some(group) = process_group {
+            child_command.process_group(group.pgid);
+        }
+        unsafe {
+            child_command.pre_exec(move || {
+                reset_child_signals(take_terminal);
+                Ok(())
+            });
+        }
+        let child_result = child_command.spawn();
         match child_result {
-            Ok(child) => Ok(Some(child)),
-            Err(_) => Err(InterpretErr::RuntimeError("Failed spawning command")),
+            Ok(child) => Ok(Stage::Running(child)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                eprintln!("{}: command not found", command);
+                Ok(Stage::Finished(127))
+            }
+            Err(err) => {
+                eprintln!("{}: {}", command, err);
+                Ok(Stage::Finished(126))
+            }
         }
     }
 
-    fn wait_handle_interrupts(&mut self, child: &mut Child) -> Result<ExitStatus, InterpretErr> {
+    // waits for a background job to finish or stop, giving up on SIGINT
+    fn wait_job(&mut self, idx: usize) -> Result<i32, InterpretErr> {
         loop {
-            match child.try_wait() {
-                Ok(None) => (),                        // Child still running
-                Ok(Some(status)) => return Ok(status), // Child finished
-                Err(_) => return Err(InterpretErr::RuntimeError("Error waiting on process")),
-            }
-            if let Ok(true) = self.sigint_receiver.try_recv() {
-                child.kill().expect("Error killing child process"); // sigint received, kill child
+            let job = self.jobs.get_mut(idx);
+            match job.state() {
+                JobState::Done(code) => return Ok(code),
+                JobState::Stopped => return Ok(128 + libc::SIGTSTP),
+                JobState::Running => job.wait(),
+            };
+            if take_interrupt() {
                 return Err(InterpretErr::Interrupt("SIGINT Received"));
             }
-            sleep(Duration::from_millis(10));
+        }
+    }
+
+    // waits for a job in the foreground until it finishes or gets stopped, and takes back
+    // the terminal afterwards. Finished jobs are removed, stopped ones stay in the table.
+    fn wait_handle_interrupts(&mut self, idx: usize) -> Result<i32, InterpretErr> {
+        let mut interrupted = false;
+        let code = loop {
+            let job = self.jobs.get_mut(idx);
+            match job.state() {
+                JobState::Done(code) => {
+                    self.jobs.remove(idx);
+                    break code;
+                }
+                JobState::Stopped => {
+                    job.stop_reported = true;
+                    println!("\n{}", job);
+                    break 128 + libc::SIGTSTP;
+                }
+                JobState::Running => job.wait(),
+            };
+            if take_interrupt() {
+                // only the foreground process group of the terminal gets SIGINT from it.
+                // With job control that was the shell, before the job took the terminal,
+                // so the job may finish without noticing
+                interrupted = self.job_control;
+                job.signal(libc::SIGINT);
+            }
+        };
+        if self.job_control {
+            give_terminal(self.shell_pgid);
+        }
+        if interrupted || code == 128 + libc::SIGINT {
+            return Err(InterpretErr::Interrupt("SIGINT Received"));
+        }
+        Ok(code)
+    }
+
+    fn kill_processes(pids: &[i32]) {
+        for pid in pids {
+            unsafe {
+                // killing a process that already exited fails, which is fine
+                libc::kill(*pid, libc::SIGKILL);
+                libc::waitpid(*pid, std::ptr::null_mut(), 0);
+            }
         }
     }
 
     fn pipeline_command(&mut self, commands: Vec<Node>) -> Result<Output, InterpretErr> {
-        let mut previous_cmd: Option<Child> = None;
+        let res = self.run_pipeline(commands);
+        self.last_status = match &res {
+            Ok(output) => Self::exit_code(output.status),
+            Err(InterpretErr::Interrupt(_)) => 130,
+            Err(InterpretErr::Break(_) | InterpretErr::Continue(_)) => 0,
+            Err(InterpretErr::Return(code)) => *code,
+            Err(_) => 1,
+        };
+        res
+    }
+
+    fn run_pipeline(&mut self, commands: Vec<Node>) -> Result<Output, InterpretErr> {
+        // spawn every stage up front so that they run concurrently, then reap them together
+        let (pids, last_code) = self.spawn_pipeline(&commands, false)?;
+        let code = match pids.first() {
+            Some(&leader) => {
+                let pgid = if self.job_control { leader } else { 0 };
+                let command = Node::Pipeline(commands).to_string();
+                let idx = self.jobs.add(Job::new(pgid, pids, command));
+                self.wait_handle_interrupts(idx)?
+            }
+            None => 0,
+        };
+        Ok(Self::new_empty_output(last_code.unwrap_or(code)))
+    }
+
+    // returns the pids of the spawned children, and the exit code of the last stage
+    // if it didn't spawn one
+    fn spawn_pipeline(
+        &mut self,
+        commands: &[Node],
+        background: bool,
+    ) -> Result<(Vec<i32>, Option<i32>), InterpretErr> {
+        let mut pids: Vec<i32> = vec![];
+        let mut previous_stdout: Option<PipeReader> = None;
+        let mut last_code = None;
         let command_count = commands.len();
         for (idx, command) in commands.iter().enumerate() {
-            let stdin = previous_cmd.map_or(Stdio::inherit(), |mut child: Child| {
-                Stdio::from(child.stdout.take().unwrap())
-            });
+            let stdin = previous_stdout.take().map(OwnedFd::from);
             let stdout = if idx < command_count - 1 {
-                Stdio::piped()
+                pipe().map(|(reader, writer)| {
+                    previous_stdout = Some(reader);
+                    Some(OwnedFd::from(writer))
+                })
             } else {
-                Stdio::inherit()
+                Ok(None)
+            };
+            // background jobs, and foreground ones under job control, get their own
+            // process group led by the first child
+            let process_group = match pids.first() {
+                _ if !(background || self.job_control) => None,
+                Some(&leader) => Some(leader),
+                None => Some(0),
+            }
+            .map(|pgid| ProcessGroup {
+                pgid,
+                foreground: !background,
+            });
+            let child_res = match (command, stdout) {
+                (_, Err(_)) => Err(InterpretErr::RuntimeError("Failed creating pipe")),
+                (Node::Command(toks, redirect), Ok(stdout)) => {
+                    self.execute_command(toks, redirect, stdin, stdout, process_group)
+                }
+                (Node::Subshell(_, redirects), Ok(stdout)) => {
+                    let run = |crsh: &mut Self| crsh.run_compound(command);
+                    self.fork_compound(redirects, stdin, stdout, process_group, run)
+                        .map(Stage::Forked)
+                }
+                (Node::FunctionDef(name, body), Ok(_)) => {
+                    self.functions.insert(name.clone(), *body.clone());
+                    Ok(Stage::Finished(0))
+                }
+                // a compound command on its own runs in the shell, in a pipeline it needs a process
+                (
+                    Node::Group(_, redirects)
+                    | Node::If(_, _, redirects)
+                    | Node::While(_, _, redirects)
+                    | Node::Until(_, _, redirects)
+                    | Node::For(_, _, _, redirects)
+                    | Node::ArithFor(_, _, _, _, redirects)
+                    | Node::Case(_, _, redirects)
+                    | Node::Select(_, _, _, redirects)
+                    | Node::Arithmetic(_, redirects),
+                    Ok(stdout),
+                ) => {
+                    let run = |crsh: &mut Self| crsh.run_compound(command);
+                    if command_count == 1 && !background {
+                        self.compound(redirects, run)
+                    } else {
+                        self.fork_compound(redirects, stdin, stdout, process_group, run)
+                            .map(Stage::Forked)
+                    }
+                }
+                _ => Err(InterpretErr::RuntimeError("Unexpected node in pipeline")),
             };
-            let mut current_cmd = match command {
-                Node::Command(toks, redirect) => {
-                    match Self::execute_command(toks, redirect, stdin, stdout) {
-                        Ok(child_opt) => child_opt,
-                        Err(err) => return Err(err),
+            let pid = match child_res {
+                Ok(Stage::Running(child)) => child.id() as i32,
+                Ok(Stage::Forked(pid)) => pid,
+                Ok(Stage::Finished(code)) => {
+                    last_code = Some(code);
+                    continue;
+                }
+                // the stage already reported why it failed, the others still run
+                Err(InterpretErr::ExitStatusFailure(_)) => {
+                    last_code = Some(1);
+                    continue;
+                }
+                Err(err) => {
+                    Self::kill_processes(&pids);
+                    if self.job_control {
+                        give_terminal(self.shell_pgid);
                     }
+                    return Err(err);
                 }
-                _ => unimplemented!("Command {:?} not implemented for pipeline", command),
             };
-            if let Some(child) = current_cmd.as_mut() {
-                self.wait_handle_interrupts(child)?;
+            if self.job_control && !background && pids.is_empty() {
+                // also done in the child, whichever runs first wins the race
+                give_terminal(pid);
             }
-            previous_cmd = current_cmd;
-        }
-        match previous_cmd {
-            Some(final_command) => final_command
-                .wait_with_output()
-                .map_err(|_| InterpretErr::RuntimeError("Error waiting for output")),
-            None => Ok(Self::new_empty_output(0)),
+            pids.push(pid);
+            last_code = None;
         }
+        Ok((pids, last_code))
+    }
+}
+
+// variables in arithmetic expressions are shell variables, or special parameters like "$#"
+impl ArithEnv for Crsh {
+    fn variable(&self, name: &str) -> Option<String> {
+        self.parameter(name)
+    }
+
+    fn set_variable(&mut self, name: &str, value: i64) {
+        self.variables.set(name, value.to_string());
+    }
+}
+
+// the message of an error without the " (os error 2)" its Display appends
+fn error_message(err: &IoError) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(idx) => message[..idx].to_string(),
+        None => message,
     }
 }
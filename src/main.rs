@@ -1,12 +1,52 @@
-use crsh::parser::Parser;
-use crsh::scanner::Scanner;
+use crsh::parser::{ParseError, Parser};
+use crsh::scanner::{ScanError, Scanner};
 use crsh::Crsh;
-use std::io::{stdin, stdout, Write};
+use std::env::args;
+use std::fs::{read_to_string, File};
+use std::io::{stdin, stdout, ErrorKind, IsTerminal, Read, Write};
+use std::iter::from_fn;
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
 use std::process::exit;
 
 fn main() {
+    let args: Vec<String> = args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("-c") => run_command_string(args),
+        Some(_) => run_script(args),
+        None if stdin().is_terminal() => interactive(),
+        // input piped into the shell runs like a script, without a prompt
+        None => run_lines(Crsh::with_job_control(false), from_fn(read_stdin_line)),
+    }
+}
+
+// reads a line of piped input byte by byte, so the lines after it are left to the
+// commands that read stdin themselves. None at the end of input.
+fn read_stdin_line() -> Option<String> {
+    // the descriptor stays open for the commands, so it's never dropped
+    let mut input = ManuallyDrop::new(unsafe { File::from_raw_fd(0) });
+    let mut line = vec![];
+    let mut byte = [0u8];
+    loop {
+        match input.read(&mut byte) {
+            Ok(1) if byte[0] != b'\n' => line.push(byte[0]),
+            Ok(1) => break,
+            Ok(_) if line.is_empty() => return None,
+            Ok(_) => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(_) => {
+                eprintln!("Error reading input");
+                exit(1);
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&line).into_owned())
+}
+
+fn interactive() {
     let mut interpreter = Crsh::new();
     loop {
+        interpreter.report_jobs();
         print!("> ");
         stdout().flush().unwrap_or_else(|_| {
             eprintln!("Error flushing output");
@@ -25,31 +65,137 @@ fn main() {
         if input.is_empty() {
             input = "exit".to_string();
         }
-        // Eval
-        let scanner = Scanner::new(input);
-        let tokens = match scanner.scan_tokens() {
-            Ok(scanner) => scanner,
-            Err(msg) => {
-                eprintln!("Scanning error: {:?}", msg);
-                continue;
+        // lines that continue the command get the prompt in $PS2
+        let continuation_prompt = interpreter.continuation_prompt();
+        run_line(&mut interpreter, input, || {
+            print!("{}", continuation_prompt);
+            let _ = stdout().flush();
+            let mut line = String::new();
+            match stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(line),
+            }
+        });
+    }
+}
+
+// runs the script with the remaining arguments as positional parameters
+// and exits with the status of its last command
+fn run_script(args: Vec<String>) {
+    let script = match read_to_string(&args[0]) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("crsh: {}: {}", args[0], err);
+            exit(127);
+        }
+    };
+    let mut interpreter = Crsh::with_job_control(false);
+    interpreter.set_positional(args);
+    run_lines(interpreter, script.lines().map(str::to_string));
+}
+
+// runs "crsh -c 'command string' [name [args]]", where name becomes "$0"
+fn run_command_string(mut args: Vec<String>) {
+    if args.len() < 2 {
+        eprintln!("crsh: -c: option requires an argument");
+        exit(2);
+    }
+    let command = args.remove(1);
+    args.remove(0);
+    let mut interpreter = Crsh::with_job_control(false);
+    interpreter.set_positional(args);
+    run_lines(interpreter, command.lines().map(str::to_string));
+}
+
+// runs the lines non-interactively and exits with the status of the last command
+fn run_lines(mut interpreter: Crsh, mut lines: impl Iterator<Item = String>) -> ! {
+    while let Some(line) = lines.next() {
+        if line.trim_start().is_empty() {
+            continue;
+        }
+        if !run_line(&mut interpreter, line, || lines.next()) {
+            exit(2);
+        }
+    }
+    exit(interpreter.last_status());
+}
+
+// scans, parses and executes a line, reading more lines while the command is incomplete,
+// as with heredocs. Returns false on a syntax error
+fn run_line(
+    interpreter: &mut Crsh,
+    mut input: String,
+    mut next_line: impl FnMut() -> Option<String>,
+) -> bool {
+    let ast = loop {
+        let tokens = loop {
+            let scanner = Scanner::new(input.clone());
+            match scanner.scan_tokens() {
+                Ok(tokens) => break tokens,
+                // reads up to a line that looks like the delimiter before scanning again,
+                // scanning after every line of a long body would take quadratic time
+                Err(ScanError::UnterminatedHereDoc(delimiter)) => loop {
+                    let Some(line) = next_line() else {
+                        eprintln!(
+                            "Scanning error: {:?}",
+                            ScanError::UnterminatedHereDoc(delimiter)
+                        );
+                        return false;
+                    };
+                    push_line(&mut input, &line);
+                    if line.trim_start_matches('\t').trim_end_matches('\n') == delimiter {
+                        break;
+                    }
+                },
+                // a quoted string or a line ending in a backslash goes on in the next line
+                Err(err @ (ScanError::UnterminatedQuote(..) | ScanError::LineContinuation)) => {
+                    match next_line() {
+                        Some(line) => push_line(&mut input, &line),
+                        None => {
+                            eprintln!("Scanning error: {:?}", err);
+                            return false;
+                        }
+                    }
+                }
+                Err(msg) => {
+                    eprintln!("Scanning error: {:?}", msg);
+                    return false;
+                }
             }
         };
         let parser = Parser::new(tokens);
         if parser.is_empty() {
-            continue;
+            return true;
         }
-        let ast = match parser.parse() {
-            Ok(parser) => parser,
+        match parser.parse() {
+            Ok(ast) => break ast,
+            // an unfinished compound command, or a line ending in "&&" or "|", goes on
+            // in the next line
+            Err(ParseError::Incomplete(msg)) => match next_line() {
+                Some(line) => push_line(&mut input, &line),
+                None => {
+                    eprintln!("Parsing error: {:?}", ParseError::Incomplete(msg));
+                    return false;
+                }
+            },
             Err(msg) => {
                 eprintln!("Parsing error: {:?}", msg);
-                continue;
-            }
-        };
-        match interpreter.execute(ast) {
-            Ok(_) => (),
-            Err(err) => {
-                eprintln!("Execution error: {}", err)
+                return false;
             }
         }
+    };
+    match interpreter.execute(ast) {
+        Ok(_) => (),
+        Err(err) => {
+            eprintln!("Execution error: {}", err)
+        }
+    }
+    true
+}
+
+fn push_line(input: &mut String, line: &str) {
+    if !input.ends_with('\n') {
+        input.push('\n');
     }
+    input.push_str(line);
 }
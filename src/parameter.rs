@@ -0,0 +1,131 @@
+use crate::ast::ReplaceMode;
+use crate::pattern::Pattern;
+
+// byte offsets of the characters of a string, followed by its length
+fn boundaries(value: &str) -> Vec<usize> {
+    value
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .chain([value.len()])
+        .collect()
+}
+
+// the end of the shortest or longest prefix the pattern matches
+fn prefix_end(value: &str, pattern: &Pattern, longest: bool) -> Option<usize> {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    ends.into_iter().find(|end| pattern.matches(&value[..*end]))
+}
+
+// the start of the shortest or longest suffix the pattern matches
+fn suffix_start(value: &str, pattern: &Pattern, longest: bool) -> Option<usize> {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    starts
+        .into_iter()
+        .find(|start| pattern.matches(&value[*start..]))
+}
+
+// "${name#pattern}" and "${name##pattern}"
+pub fn remove_prefix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    match prefix_end(value, pattern, longest) {
+        Some(end) => value[end..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+// "${name%pattern}" and "${name%%pattern}"
+pub fn remove_suffix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    match suffix_start(value, pattern, longest) {
+        Some(start) => value[..start].to_string(),
+        None => value.to_string(),
+    }
+}
+
+// replaces the longest text the pattern matches, the first one or all of them. Matches
+// anywhere but at the start or end of the value have to be non-empty.
+pub fn replace(value: &str, pattern: &Pattern, replacement: &str, mode: ReplaceMode) -> String {
+    match mode {
+        ReplaceMode::Prefix => {
+            return match prefix_end(value, pattern, true) {
+                Some(end) => format!("{}{}", replacement, &value[end..]),
+                None => value.to_string(),
+            }
+        }
+        ReplaceMode::Suffix => {
+            return match suffix_start(value, pattern, true) {
+                Some(start) => format!("{}{}", &value[..start], replacement),
+                None => value.to_string(),
+            }
+        }
+        ReplaceMode::First | ReplaceMode::All => (),
+    }
+    let bounds = boundaries(value);
+    let mut replaced = String::new();
+    let mut idx = 0;
+    while idx + 1 < bounds.len() {
+        let start = bounds[idx];
+        let end = bounds[idx + 1..]
+            .iter()
+            .rposition(|end| pattern.matches(&value[start..*end]));
+        match end {
+            Some(end) => {
+                replaced.push_str(replacement);
+                idx += end + 1;
+                if mode == ReplaceMode::First {
+                    replaced.push_str(&value[bounds[idx]..]);
+                    return replaced;
+                }
+            }
+            None => {
+                replaced.push_str(&value[start..bounds[idx + 1]]);
+                idx += 1;
+            }
+        }
+    }
+    replaced
+}
+
+// "${name:offset:length}" in characters. A negative offset counts from the end, and so
+// does a negative length, which is where the substring ends then. None if that end comes
+// before the start.
+pub fn substring(value: &str, offset: i64, length: Option<i64>) -> Option<String> {
+    let chars: Vec<char> = value.chars().collect();
+    let count = chars.len() as i64;
+    let start = if offset < 0 { count + offset } else { offset };
+    if !(0..=count).contains(&start) {
+        return Some(String::new());
+    }
+    let end = match length {
+        None => count,
+        Some(length) if length < 0 => count + length,
+        Some(length) => start.saturating_add(length).min(count),
+    };
+    if end < start {
+        return None;
+    }
+    Some(chars[start as usize..end as usize].iter().collect())
+}
+
+// "${name^}" and "${name,}" change the first character, "${name^^}" and "${name,,}" all
+pub fn change_case(value: &str, upper: bool, all: bool) -> String {
+    let convert = |chr: char| -> String {
+        if upper {
+            chr.to_uppercase().collect()
+        } else {
+            chr.to_lowercase().collect()
+        }
+    };
+    if all {
+        return value.chars().map(convert).collect();
+    }
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => convert(first) + chars.as_str(),
+        None => String::new(),
+    }
+}
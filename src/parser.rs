@@ -1,29 +1,32 @@
-use crate::ast::Node;
+use crate::ast::{CaseItem, CaseTerminator, Node, WordPart};
+use crate::brace::brace_expansion;
 use crate::token::Token;
-use lazy_static::lazy_static;
+use crate::variables::is_valid_name;
 use std::mem::discriminant;
 
-lazy_static! {
-    static ref REGULAR_TOKEN: Token = Token::Regular("".to_string());
-}
-
 #[derive(Debug)]
 pub enum ParseError {
     TokensNotParsed(&'static str),
     NotExpectedToken(&'static str),
     IndexOutOfBounds(&'static str),
+    // the input ends in the middle of a command, which the next lines may complete
+    Incomplete(&'static str),
 }
 
-macro_rules! unwrap_regular {
-    ($x:expr) => {{
-        if let Token::Regular(string) = $x {
-            string
-        } else {
-            return Err(ParseError::NotExpectedToken("Token is not Regular Token"));
-        }
-    }};
+// words that are special at the start of a command, unless they are quoted
+pub const RESERVED_WORDS: [&str; 17] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "do", "done", "for", "select", "in",
+    "case", "esac", "function", "{", "}",
+];
+
+// a reserved word like "then", which is scanned as a regular word
+fn reserved(word: &str) -> Token {
+    Token::Regular(word.to_string())
 }
 
+// builds a redirect node from its file descriptor and target
+type RedirectNode = fn(i32, Box<Node>) -> Node;
+
 pub struct Parser {
     tokens: Vec<Token>,
     curr: usize,
@@ -35,13 +38,42 @@ impl Parser {
     }
 
     pub fn parse(mut self) -> Result<Node, ParseError> {
-        let mut pipelines = vec![self.pipeline()?];
-        while self.match_tok(&Token::CommandSeparator)? {
-            pipelines.push(self.pipeline()?);
-        }
+        let node = self.list(&[])?;
         if self.peek()? != &Token::EOF {
             return Err(ParseError::TokensNotParsed("Not all tokens are parsed"));
         }
+        Ok(node)
+    }
+
+    // whether there are no commands, only empty lines
+    pub fn is_empty(&self) -> bool {
+        self.tokens
+            .iter()
+            .all(|token| matches!(token, Token::Newline | Token::EOF))
+    }
+
+    // parses and-or lists separated by ";", "&" or newlines, up to the end of the input or one
+    // of the tokens that end the list. Those are reserved words like "fi" in compound commands.
+    fn list(&mut self, ends: &[Token]) -> Result<Node, ParseError> {
+        let mut pipelines = vec![];
+        loop {
+            self.skip_newlines()?;
+            // the last command may be followed by a separator
+            if self.check_tok(&Token::EOF)? || ends.contains(self.peek()?) {
+                break;
+            }
+            let node = self.and_or()?;
+            if self.match_tok(&Token::Background)? {
+                pipelines.push(Node::Background(Box::new(node)));
+            } else if self.match_tok(&Token::CommandSeparator)?
+                || self.match_tok(&Token::Newline)?
+            {
+                pipelines.push(node);
+            } else {
+                pipelines.push(node);
+                break;
+            }
+        }
         if pipelines.len() == 1 {
             Ok(pipelines.pop().unwrap())
         } else {
@@ -49,51 +81,511 @@ impl Parser {
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.tokens == vec![Token::EOF]
+    fn and_or(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.pipeline()?;
+        loop {
+            if self.match_tok(&Token::And)? {
+                self.linebreak()?;
+                node = Node::And(Box::new(node), Box::new(self.pipeline()?));
+            } else if self.match_tok(&Token::Or)? {
+                self.linebreak()?;
+                node = Node::Or(Box::new(node), Box::new(self.pipeline()?));
+            } else {
+                return Ok(node);
+            }
+        }
     }
 
     fn pipeline(&mut self) -> Result<Node, ParseError> {
         let mut commands = vec![self.command()?];
         while self.match_tok(&Token::Pipe)? {
+            self.linebreak()?;
             commands.push(self.command()?);
         }
         Ok(Node::Pipeline(commands))
     }
 
     fn command(&mut self) -> Result<Node, ParseError> {
+        if self.match_tok(&Token::SubshellStart)? {
+            let (body, _) = self.compound_list(&[Token::SubshellEnd])?;
+            return Ok(Node::Subshell(Box::new(body), self.redirects()?));
+        }
+        if let Token::Arithmetic(expression) = self.peek()? {
+            let expression = expression.clone();
+            self.advance();
+            return Ok(Node::Arithmetic(expression, self.redirects()?));
+        }
+        // reserved words are only special at the start of a command, so "echo if }" is fine
+        match self.peek()? {
+            Token::Regular(word) if word == "{" => {
+                self.advance();
+                let (body, _) = self.compound_list(&[reserved("}")])?;
+                return Ok(Node::Group(Box::new(body), self.redirects()?));
+            }
+            Token::Regular(word) if word == "if" => {
+                self.advance();
+                return self.if_command();
+            }
+            Token::Regular(word) if word == "while" || word == "until" => {
+                let until = word == "until";
+                self.advance();
+                let (condition, _) = self.compound_list(&[reserved("do")])?;
+                let (body, _) = self.compound_list(&[reserved("done")])?;
+                let (condition, body) = (Box::new(condition), Box::new(body));
+                let redirects = self.redirects()?;
+                if until {
+                    return Ok(Node::Until(condition, body, redirects));
+                }
+                return Ok(Node::While(condition, body, redirects));
+            }
+            Token::Regular(word) if word == "for" || word == "select" => {
+                let select = word == "select";
+                self.advance();
+                return self.for_command(select);
+            }
+            Token::Regular(word) if word == "case" => {
+                self.advance();
+                return self.case_command();
+            }
+            Token::Regular(word) if word == "function" => {
+                self.advance();
+                return self.function_definition(true);
+            }
+            Token::Regular(_)
+                if self.tokens.get(self.curr + 1) == Some(&Token::SubshellStart)
+                    && self.tokens.get(self.curr + 2) == Some(&Token::SubshellEnd) =>
+            {
+                return self.function_definition(false);
+            }
+            _ => (),
+        }
         let mut command = vec![];
-        while self.check_tok(&REGULAR_TOKEN)? {
-            let string = unwrap_regular!(self.advance());
-            // TODO no-copy approach instead?
-            command.push(string.clone());
-        }
-        let mut redirect = vec![];
-        while self.check_tok(&Token::RRedirect)? || self.check_tok(&Token::LRedirect)? {
-            let tok = self.advance();
-            match tok {
-                Token::LRedirect => {
-                    let string = unwrap_regular!(self.advance());
-                    redirect.push(Node::RedirectRead(string.clone()))
+        // assignments before the command name only apply to that command
+        while let Token::Assignment(_) | Token::AppendAssignment(_) = self.peek()? {
+            command.push(self.assignment()?);
+        }
+        let declaration = matches!(
+            self.peek()?,
+            Token::Regular(word) if ["declare", "local", "export"].contains(&word.as_str())
+        );
+        loop {
+            // after "declare", "local" and "export" assignments are expanded like the ones
+            // before a command, without splitting, as in "local out=$(cmd)" or "local arr=(a b)"
+            if declaration
+                && matches!(
+                    self.peek()?,
+                    Token::Assignment(_) | Token::AppendAssignment(_)
+                )
+            {
+                command.push(self.assignment()?);
+                continue;
+            }
+            match self.word()? {
+                Some(word) => command.extend(Self::braces(word)),
+                None => break,
+            }
+        }
+        let redirects = self.redirects()?;
+        if command.is_empty() && redirects.is_empty() {
+            return Err(ParseError::NotExpectedToken("Expected command"));
+        }
+        Ok(Node::Command(command, redirects))
+    }
+
+    // parses the rest of "if list; then list; [elif list; then list;]... [else list;] fi"
+    fn if_command(&mut self) -> Result<Node, ParseError> {
+        let mut branches = vec![];
+        loop {
+            let (condition, _) = self.compound_list(&[reserved("then")])?;
+            let ends = [reserved("elif"), reserved("else"), reserved("fi")];
+            let (body, end) = self.compound_list(&ends)?;
+            branches.push((condition, body));
+            if end == reserved("else") {
+                let (body, _) = self.compound_list(&[reserved("fi")])?;
+                return Ok(Node::If(branches, Some(Box::new(body)), self.redirects()?));
+            }
+            if end == reserved("fi") {
+                return Ok(Node::If(branches, None, self.redirects()?));
+            }
+        }
+    }
+
+    // parses the rest of "for name [in words]; do list; done", "for ((init; condition; step));
+    // do list; done" or "select name [in words]; do list; done"
+    fn for_command(&mut self, select: bool) -> Result<Node, ParseError> {
+        if let (Token::Arithmetic(expression), false) = (self.peek()?, select) {
+            let expressions: Vec<String> = expression.split(';').map(str::to_string).collect();
+            let [init, condition, step] = <[String; 3]>::try_from(expressions).map_err(|_| {
+                ParseError::NotExpectedToken("Expected three expressions in \"for ((...))\"")
+            })?;
+            self.advance();
+            self.match_tok(&Token::CommandSeparator)?;
+            let body = Box::new(self.do_group()?);
+            return Ok(Node::ArithFor(
+                init,
+                condition,
+                step,
+                body,
+                self.redirects()?,
+            ));
+        }
+        let name = match self.peek()? {
+            Token::Regular(name) if is_valid_name(name) => name.clone(),
+            _ => {
+                return Err(ParseError::NotExpectedToken(
+                    "Expected variable name after \"for\"",
+                ))
+            }
+        };
+        self.advance();
+        self.skip_newlines()?;
+        let words = if self.peek()? == &reserved("in") {
+            self.advance();
+            let mut words = vec![];
+            while let Some(word) = self.word()? {
+                words.extend(Self::braces(word));
+            }
+            if self.check_tok(&Token::EOF)? {
+                return Err(ParseError::Incomplete("Expected \"do\""));
+            }
+            if !(self.match_tok(&Token::CommandSeparator)? || self.match_tok(&Token::Newline)?) {
+                return Err(ParseError::NotExpectedToken(
+                    "Expected \";\" or newline after the words of \"for\"",
+                ));
+            }
+            Some(words)
+        } else {
+            // without "in" the loop goes over the positional parameters
+            self.match_tok(&Token::CommandSeparator)?;
+            None
+        };
+        let body = Box::new(self.do_group()?);
+        let redirects = self.redirects()?;
+        if select {
+            return Ok(Node::Select(name, words, body, redirects));
+        }
+        Ok(Node::For(name, words, body, redirects))
+    }
+
+    // parses "do list; done", the body of for and select loops
+    fn do_group(&mut self) -> Result<Node, ParseError> {
+        self.skip_newlines()?;
+        if self.check_tok(&Token::EOF)? {
+            return Err(ParseError::Incomplete("Expected \"do\""));
+        }
+        if self.peek()? != &reserved("do") {
+            return Err(ParseError::NotExpectedToken("Expected \"do\""));
+        }
+        self.advance();
+        let (body, _) = self.compound_list(&[reserved("done")])?;
+        Ok(body)
+    }
+
+    // parses the rest of "case word in [(]pattern [| pattern]...) list ;; ... esac", where
+    // the last item doesn't need a terminator
+    fn case_command(&mut self) -> Result<Node, ParseError> {
+        let word = self
+            .word()?
+            .ok_or(ParseError::NotExpectedToken("Expected word after \"case\""))?;
+        self.skip_newlines()?;
+        if self.peek()? != &reserved("in") {
+            return Err(ParseError::NotExpectedToken(
+                "Expected \"in\" after case word",
+            ));
+        }
+        self.advance();
+        let mut items = vec![];
+        loop {
+            self.skip_newlines()?;
+            if self.check_tok(&Token::EOF)? {
+                return Err(ParseError::Incomplete("Expected \"esac\""));
+            }
+            if self.peek()? == &reserved("esac") {
+                self.advance();
+                break;
+            }
+            self.match_tok(&Token::SubshellStart)?;
+            let mut patterns = vec![];
+            loop {
+                patterns.push(
+                    self.word()?
+                        .ok_or(ParseError::NotExpectedToken("Expected case pattern"))?,
+                );
+                if !self.match_tok(&Token::Pipe)? {
+                    break;
                 }
-                Token::RRedirect => match self.advance() {
-                    Token::RRedirect => {
-                        let string = unwrap_regular!(self.advance());
-                        redirect.push(Node::RedirectAppend(string.clone()));
-                    }
-                    Token::Regular(string) => {
-                        redirect.push(Node::RedirectWrite(string.clone()));
-                    }
-                    _ => return Err(ParseError::NotExpectedToken("Unexpected token after \">\"")),
-                },
+            }
+            if !self.match_tok(&Token::SubshellEnd)? {
+                return Err(ParseError::NotExpectedToken(
+                    "Expected \")\" after case pattern",
+                ));
+            }
+            let ends = [
+                Token::CaseBreak,
+                Token::CaseFallThrough,
+                Token::CaseContinue,
+                reserved("esac"),
+            ];
+            let body = self.list(&ends)?;
+            let terminator = match self.peek()? {
+                Token::CaseFallThrough => CaseTerminator::FallThrough,
+                Token::CaseContinue => CaseTerminator::Continue,
+                Token::CaseBreak => CaseTerminator::Break,
+                // the "esac" is matched on the next round
+                token if token == &reserved("esac") => {
+                    items.push(CaseItem {
+                        patterns,
+                        body,
+                        terminator: CaseTerminator::Break,
+                    });
+                    continue;
+                }
+                Token::EOF => return Err(ParseError::Incomplete("Expected \"esac\"")),
                 _ => {
                     return Err(ParseError::NotExpectedToken(
-                        "Unexpected token while attempting to match Redirect",
+                        "Expected \";;\" after case item",
                     ))
                 }
+            };
+            self.advance();
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
+        }
+        Ok(Node::Case(Box::new(word), items, self.redirects()?))
+    }
+
+    fn assignment(&mut self) -> Result<Node, ParseError> {
+        let (name, append) = match self.advance() {
+            Token::Assignment(name) => (name.clone(), false),
+            Token::AppendAssignment(name) => (name.clone(), true),
+            _ => return Err(ParseError::NotExpectedToken("Expected assignment")),
+        };
+        let value = if self.match_tok(&Token::SubshellStart)? {
+            self.array()?
+        } else {
+            self.word()?.ok_or(ParseError::NotExpectedToken(
+                "Expected value after assignment",
+            ))?
+        };
+        if append {
+            return Ok(Node::AppendAssignment(name, Box::new(value)));
+        }
+        Ok(Node::Assignment(name, Box::new(value)))
+    }
+
+    // parses the elements of "name=(a [5]=b)" after the "(", which the scanner may have
+    // ended early if the input ends before the ")"
+    fn array(&mut self) -> Result<Node, ParseError> {
+        let mut elements = vec![];
+        loop {
+            let subscript = match self.peek()? {
+                Token::SubshellEnd => {
+                    self.advance();
+                    return Ok(Node::Array(elements));
+                }
+                Token::EOF => return Err(ParseError::Incomplete("Expected \")\" after array")),
+                Token::Subscript(subscript) => {
+                    let subscript = subscript.clone();
+                    self.advance();
+                    Some(subscript)
+                }
+                _ => None,
+            };
+            let word = self
+                .word()?
+                .ok_or(ParseError::NotExpectedToken("Expected word in array"))?;
+            match subscript {
+                Some(_) => elements.push((subscript, word)),
+                None => elements.extend(Self::braces(word).into_iter().map(|word| (None, word))),
+            }
+        }
+    }
+
+    // parses "name() compound-command", or "function name [()] compound-command" after the
+    // keyword. The body can start on the next line.
+    fn function_definition(&mut self, keyword: bool) -> Result<Node, ParseError> {
+        let name = match self.advance() {
+            Token::Regular(name) => name.clone(),
+            Token::EOF if keyword => return Err(ParseError::Incomplete("Expected function name")),
+            _ => return Err(ParseError::NotExpectedToken("Expected function name")),
+        };
+        if self.match_tok(&Token::SubshellStart)? {
+            if !self.match_tok(&Token::SubshellEnd)? {
+                return Err(ParseError::NotExpectedToken(
+                    "Expected ) after function name",
+                ));
+            }
+        } else if !keyword {
+            return Err(ParseError::NotExpectedToken(
+                "Expected () after function name",
+            ));
+        }
+        self.linebreak()?;
+        let body = self.command()?;
+        if matches!(body, Node::Command(..) | Node::FunctionDef(..)) {
+            return Err(ParseError::NotExpectedToken(
+                "Expected compound command as function body",
+            ));
+        }
+        Ok(Node::FunctionDef(name, Box::new(body)))
+    }
+
+    // the commands of a compound command up to the token that ends them, which is returned
+    fn compound_list(&mut self, ends: &[Token]) -> Result<(Node, Token), ParseError> {
+        let body = self.list(ends)?;
+        let end = self.peek()?.clone();
+        if end == Token::EOF {
+            return Err(ParseError::Incomplete("Expected end of compound command"));
+        }
+        if !ends.contains(&end) {
+            return Err(ParseError::NotExpectedToken(
+                "Unexpected token in compound command",
+            ));
+        }
+        if body == Node::CommandSequence(vec![]) {
+            return Err(ParseError::NotExpectedToken(
+                "Expected command in compound command",
+            ));
+        }
+        self.advance();
+        Ok((body, end))
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while self.match_tok(&Token::Newline)? {}
+        Ok(())
+    }
+
+    // skips newlines after "&&", "||" or "|", the command after them may be on the next line
+    fn linebreak(&mut self) -> Result<(), ParseError> {
+        self.skip_newlines()?;
+        if self.check_tok(&Token::EOF)? {
+            return Err(ParseError::Incomplete("Expected command"));
+        }
+        Ok(())
+    }
+
+    fn redirects(&mut self) -> Result<Vec<Node>, ParseError> {
+        let mut redirects = vec![];
+        while self.redirect(&mut redirects)? {}
+        Ok(redirects)
+    }
+
+    // parses a redirect with an optional file descriptor, returns false if there is none.
+    // "&>" and "&>>" become a redirect of stdout followed by "2>&1".
+    fn redirect(&mut self, redirects: &mut Vec<Node>) -> Result<bool, ParseError> {
+        let fd = match self.peek()? {
+            Token::IoNumber(fd) => {
+                let fd = *fd;
+                self.advance();
+                Some(fd)
             }
+            _ => None,
+        };
+        if let Token::HereDoc(delimiter, body) = self.peek()? {
+            let delimiter = delimiter.clone();
+            let body = match body.as_slice() {
+                [] => Node::Word(String::new()),
+                [WordPart::Literal(body)] => Node::Word(body.clone()),
+                _ => Node::ExpandableWord(body.clone()),
+            };
+            self.advance();
+            let fd = fd.unwrap_or(0);
+            redirects.push(Node::RedirectHereDoc(fd, delimiter, Box::new(body)));
+            return Ok(true);
+        }
+        let (default_fd, node): (i32, RedirectNode) = match self.peek()? {
+            Token::LRedirect => (0, Node::RedirectRead),
+            Token::RRedirect | Token::RedirectAll => (1, Node::RedirectWrite),
+            Token::RAppend | Token::AppendAll => (1, Node::RedirectAppend),
+            Token::ReadWrite => (0, Node::RedirectReadWrite),
+            Token::HereString => (0, Node::RedirectHereString),
+            Token::LDuplicate => (0, Node::RedirectDuplicate),
+            Token::RDuplicate => (1, Node::RedirectDuplicate),
+            _ if fd.is_some() => {
+                return Err(ParseError::NotExpectedToken(
+                    "Expected redirect after file descriptor",
+                ))
+            }
+            _ => return Ok(false),
+        };
+        let all = matches!(self.advance(), Token::RedirectAll | Token::AppendAll);
+        let target = self
+            .word()?
+            .ok_or(ParseError::NotExpectedToken("Expected word after redirect"))?;
+        let target = match Self::braces(target).as_slice() {
+            [target] => target.clone(),
+            _ => return Err(ParseError::NotExpectedToken("Ambiguous redirect")),
+        };
+        redirects.push(node(fd.unwrap_or(default_fd), Box::new(target)));
+        if all {
+            redirects.push(Node::RedirectDuplicate(2, Box::new(Node::Word("1".into()))));
         }
-        Ok(Node::Command(command, redirect))
+        Ok(true)
+    }
+
+    fn word(&mut self) -> Result<Option<Node>, ParseError> {
+        // TODO no-copy approach instead?
+        let word = match self.peek()? {
+            Token::Regular(string) => Node::Word(string.clone()),
+            Token::Expansion(parts) => Node::ExpandableWord(parts.clone()),
+            Token::Assignment(name) | Token::AppendAssignment(name) => {
+                // after the command name "NAME=value" is an ordinary word
+                let operator = if matches!(self.peek()?, Token::AppendAssignment(_)) {
+                    "+="
+                } else {
+                    "="
+                };
+                let prefix = format!("{}{}", name, operator);
+                self.advance();
+                return match self.word()? {
+                    Some(Node::Word(value)) => Ok(Some(Node::Word(prefix + &value))),
+                    Some(Node::ExpandableWord(mut parts)) => {
+                        parts.insert(0, WordPart::Literal(prefix));
+                        Ok(Some(Node::ExpandableWord(parts)))
+                    }
+                    _ => Err(ParseError::NotExpectedToken(
+                        "Expected value after assignment",
+                    )),
+                };
+            }
+            _ => return Ok(None),
+        };
+        self.advance();
+        Ok(Some(word))
+    }
+
+    // expands the braces in a word, alternatives that end up empty are dropped as in "x{,}"
+    fn braces(word: Node) -> Vec<Node> {
+        let Node::ExpandableWord(parts) = &word else {
+            return vec![word];
+        };
+        if !parts.iter().any(|part| matches!(part, WordPart::Brace(_))) {
+            return vec![word];
+        }
+        brace_expansion(parts)
+            .into_iter()
+            .filter_map(|parts| {
+                let mut merged: Vec<WordPart> = vec![];
+                for part in parts {
+                    match (merged.last_mut(), part) {
+                        (Some(WordPart::Literal(last)), WordPart::Literal(literal)) => {
+                            last.push_str(&literal)
+                        }
+                        (_, part) => merged.push(part),
+                    }
+                }
+                match merged.as_slice() {
+                    [] => None,
+                    [WordPart::Literal(literal)] if literal.is_empty() => None,
+                    [WordPart::Literal(literal)] => Some(Node::Word(literal.clone())),
+                    _ => Some(Node::ExpandableWord(merged)),
+                }
+            })
+            .collect()
     }
 
     fn check_tok(&self, token: &Token) -> Result<bool, ParseError> {
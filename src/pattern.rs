@@ -0,0 +1,197 @@
+// shell patterns as used by globbing, where a backslash makes the next character literal
+#[derive(Clone, Debug, PartialEq)]
+enum PatternToken {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Bracket(Bracket),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Bracket {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+    classes: Vec<String>,
+}
+
+impl Bracket {
+    fn matches(&self, chr: char) -> bool {
+        let found = self
+            .ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&chr))
+            || self.classes.iter().any(|class| class_matches(class, chr));
+        found != self.negated
+    }
+}
+
+fn class_matches(class: &str, chr: char) -> bool {
+    match class {
+        "alnum" => chr.is_alphanumeric(),
+        "alpha" => chr.is_alphabetic(),
+        "blank" => chr == ' ' || chr == '\t',
+        "cntrl" => chr.is_control(),
+        "digit" => chr.is_ascii_digit(),
+        "graph" => chr.is_ascii_graphic(),
+        "lower" => chr.is_lowercase(),
+        "print" => chr.is_ascii_graphic() || chr == ' ',
+        "punct" => chr.is_ascii_punctuation(),
+        "space" => chr.is_whitespace(),
+        "upper" => chr.is_uppercase(),
+        "xdigit" => chr.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<PatternToken>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = vec![];
+        let mut idx = 0;
+        while idx < chars.len() {
+            let token = match chars[idx] {
+                '\\' if idx + 1 < chars.len() => {
+                    idx += 1;
+                    PatternToken::Char(chars[idx])
+                }
+                '*' => PatternToken::AnyString,
+                '?' => PatternToken::AnyChar,
+                // a "[" without a closing "]" is an ordinary character
+                '[' => match Self::bracket(&chars, idx + 1) {
+                    Some((bracket, end)) => {
+                        idx = end;
+                        PatternToken::Bracket(bracket)
+                    }
+                    None => PatternToken::Char('['),
+                },
+                chr => PatternToken::Char(chr),
+            };
+            tokens.push(token);
+            idx += 1;
+        }
+        Self { tokens }
+    }
+
+    // parses a bracket expression starting after the "[", returns it with the index of the "]"
+    fn bracket(chars: &[char], start: usize) -> Option<(Bracket, usize)> {
+        let mut idx = start;
+        let negated = matches!(chars.get(idx), Some('!' | '^'));
+        if negated {
+            idx += 1;
+        }
+        let mut bracket = Bracket {
+            negated,
+            ranges: vec![],
+            classes: vec![],
+        };
+        // a "]" right at the start is part of the set
+        let first = idx;
+        loop {
+            let mut chr = *chars.get(idx)?;
+            match chr {
+                ']' if idx > first => return Some((bracket, idx)),
+                '[' if chars.get(idx + 1) == Some(&':') => {
+                    let name_start = idx + 2;
+                    let name_len = chars[name_start..]
+                        .windows(2)
+                        .position(|pair| pair == [':', ']'])?;
+                    let name = chars[name_start..name_start + name_len].iter().collect();
+                    bracket.classes.push(name);
+                    idx = name_start + name_len + 2;
+                    continue;
+                }
+                '\\' => {
+                    idx += 1;
+                    chr = *chars.get(idx)?;
+                }
+                _ => (),
+            }
+            // "a-z" is a range, a "-" at the end is an ordinary character
+            if chars.get(idx + 1) == Some(&'-') && !matches!(chars.get(idx + 2), Some(']') | None) {
+                let mut end_idx = idx + 2;
+                if chars[end_idx] == '\\' {
+                    end_idx += 1;
+                }
+                bracket.ranges.push((chr, *chars.get(end_idx)?));
+                idx = end_idx + 1;
+            } else {
+                bracket.ranges.push((chr, chr));
+                idx += 1;
+            }
+        }
+    }
+
+    // whether the pattern only matches one string, because it has no special characters
+    pub fn is_literal(&self) -> bool {
+        self.tokens
+            .iter()
+            .all(|token| matches!(token, PatternToken::Char(_)))
+    }
+
+    // the string a literal pattern matches
+    pub fn literal(&self) -> String {
+        self.tokens
+            .iter()
+            .filter_map(|token| match token {
+                PatternToken::Char(chr) => Some(*chr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // whether the pattern starts with a literal ".", which is needed to match hidden files
+    pub fn starts_with_dot(&self) -> bool {
+        self.tokens.first() == Some(&PatternToken::Char('.'))
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let (mut token_idx, mut char_idx) = (0, 0);
+        // where to continue if the text doesn't match after the last "*"
+        let mut backtrack = None;
+        while char_idx < chars.len() {
+            let matched = match self.tokens.get(token_idx) {
+                Some(PatternToken::AnyString) => {
+                    backtrack = Some((token_idx, char_idx));
+                    token_idx += 1;
+                    continue;
+                }
+                Some(PatternToken::Char(chr)) => *chr == chars[char_idx],
+                Some(PatternToken::AnyChar) => true,
+                Some(PatternToken::Bracket(bracket)) => bracket.matches(chars[char_idx]),
+                None => false,
+            };
+            if matched {
+                token_idx += 1;
+                char_idx += 1;
+            } else if let Some((star_idx, star_char_idx)) = backtrack {
+                // let the "*" match one more character
+                token_idx = star_idx + 1;
+                char_idx = star_char_idx + 1;
+                backtrack = Some((star_idx, char_idx));
+            } else {
+                return false;
+            }
+        }
+        self.tokens[token_idx..]
+            .iter()
+            .all(|token| *token == PatternToken::AnyString)
+    }
+}
+
+// escapes the characters that are special in patterns, so the string only matches itself
+pub fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for chr in string.chars() {
+        if "*?[]\\".contains(chr) {
+            escaped.push('\\');
+        }
+        escaped.push(chr);
+    }
+    escaped
+}
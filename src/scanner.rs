@@ -1,19 +1,42 @@
+use crate::ast::{Node, ParameterOperator, ReplaceMode, WordPart};
+use crate::brace::sequence;
+use crate::parser::{Parser, RESERVED_WORDS};
 use crate::token::Token;
+use crate::variables::{is_valid_name, split_subscript};
 use lazy_static::lazy_static;
 use std::collections::HashSet;
+use std::mem::take;
 
 lazy_static! {
     static ref SPECIAL_CHARACTERS: HashSet<char> = "$'\"\\#=[]!><|;{}()*?~&".chars().collect();
+    static ref SPECIAL_PARAMETERS: HashSet<char> = "?!#@*0123456789".chars().collect();
 }
 #[derive(Debug)]
 pub enum ScanError {
     EmptyToken(&'static str),
     IndexOutOfBounds(&'static str),
+    BadSubstitution(&'static str),
+    // the input ends before the line with this heredoc delimiter
+    UnterminatedHereDoc(String),
+    // the input ends inside the quote that starts at this character
+    UnterminatedQuote(char, usize),
+    // the input ends with a backslash, which continues the line
+    LineContinuation,
 }
+
+// a heredoc whose body starts after the end of the current line
+struct PendingHereDoc {
+    token: usize,
+    delimiter: String,
+    strip_tabs: bool,
+    expand: bool,
+}
+
 pub struct Scanner {
     chars: Vec<char>,
     curr: usize,
     tokens: Vec<Token>,
+    pending_heredocs: Vec<PendingHereDoc>,
 }
 
 impl Scanner {
@@ -22,6 +45,7 @@ impl Scanner {
             chars: source.chars().collect(),
             curr: 0,
             tokens: vec![],
+            pending_heredocs: vec![],
         }
     }
 
@@ -32,6 +56,9 @@ impl Scanner {
                 self.tokens.push(token);
             }
         }
+        if let Some(heredoc) = self.pending_heredocs.pop() {
+            return Err(ScanError::UnterminatedHereDoc(heredoc.delimiter));
+        }
         self.tokens.push(Token::EOF);
         Ok(self.tokens)
     }
@@ -44,42 +71,997 @@ impl Scanner {
             }};
         }
 
-        let chr = self.peek()?;
+        let chr = *self.peek()?;
         match chr {
-            '|' => advance_return!(Token::Pipe),
-            ' ' | '\t' | '\n' | '\r' => self.whitespace(),
-            '<' => advance_return!(Token::LRedirect),
-            '>' => advance_return!(Token::RRedirect),
-            ';' => advance_return!(Token::CommandSeparator),
-            '"' | '\'' => self.quoted_token(),
-            _ => self.regular_token(),
+            '|' => {
+                self.advance()?;
+                if self.match_char('|') {
+                    return Ok(Some(Token::Or));
+                }
+                Ok(Some(Token::Pipe))
+            }
+            '&' => {
+                self.advance()?;
+                if self.match_char('&') {
+                    return Ok(Some(Token::And));
+                }
+                if self.match_char('>') {
+                    if self.match_char('>') {
+                        return Ok(Some(Token::AppendAll));
+                    }
+                    return Ok(Some(Token::RedirectAll));
+                }
+                Ok(Some(Token::Background))
+            }
+            '\n' => {
+                self.advance()?;
+                if !self.pending_heredocs.is_empty() {
+                    self.heredoc_bodies()?;
+                }
+                Ok(Some(Token::Newline))
+            }
+            ' ' | '\t' | '\r' => self.whitespace(),
+            '\\' if self.line_continuation()? => Ok(None),
+            // "#" only starts a comment at the start of a word, "a#b" is a word
+            '#' => {
+                self.comment();
+                Ok(None)
+            }
+            '<' => {
+                self.advance()?;
+                if self.match_char('<') {
+                    if self.match_char('<') {
+                        return Ok(Some(Token::HereString));
+                    }
+                    return self.heredoc();
+                }
+                if self.match_char('>') {
+                    return Ok(Some(Token::ReadWrite));
+                }
+                if self.match_char('&') {
+                    return Ok(Some(Token::LDuplicate));
+                }
+                Ok(Some(Token::LRedirect))
+            }
+            '>' => {
+                self.advance()?;
+                if self.match_char('>') {
+                    return Ok(Some(Token::RAppend));
+                }
+                if self.match_char('&') {
+                    return Ok(Some(Token::RDuplicate));
+                }
+                Ok(Some(Token::RRedirect))
+            }
+            ';' => {
+                self.advance()?;
+                if self.match_char(';') {
+                    if self.match_char('&') {
+                        return Ok(Some(Token::CaseContinue));
+                    }
+                    return Ok(Some(Token::CaseBreak));
+                }
+                if self.match_char('&') {
+                    return Ok(Some(Token::CaseFallThrough));
+                }
+                Ok(Some(Token::CommandSeparator))
+            }
+            '(' => {
+                if let Some(expression) = self.arithmetic_command() {
+                    return Ok(Some(Token::Arithmetic(expression)));
+                }
+                advance_return!(Token::SubshellStart)
+            }
+            ')' => advance_return!(Token::SubshellEnd),
+            _ => self.regular_token(false),
+        }
+    }
+
+    // the parts of a double quoted string after the opening quote. Unlike single quotes,
+    // double quotes still allow expansions, and a backslash escapes "$", "`", """, "\\"
+    // and newlines.
+    fn double_quoted(&mut self) -> Result<Vec<WordPart>, ScanError> {
+        let start = self.curr - 1;
+        let mut parts = vec![];
+        while !(self.is_end() || *self.peek()? == '"') {
+            match *self.peek()? {
+                '$' => self.expansion(&mut parts)?,
+                '`' => self.backquote(&mut parts)?,
+                '\\' => {
+                    self.advance()?;
+                    match self.peek() {
+                        Ok('\n') => {
+                            self.advance()?;
+                        }
+                        Ok('$' | '`' | '"' | '\\') => {
+                            let chr = *self.advance()?;
+                            Self::push_literal(&mut parts, chr);
+                        }
+                        _ => Self::push_literal(&mut parts, '\\'),
+                    }
+                }
+                _ => {
+                    let chr = *self.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                }
+            }
+        }
+        if !self.match_char('"') {
+            return Err(ScanError::UnterminatedQuote('"', start));
+        }
+        Ok(parts)
+    }
+
+    // the text of a single quoted string after the opening quote, without any escapes
+    fn single_quoted(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        let start = self.curr - 1;
+        while !(self.is_end() || *self.peek()? == '\'') {
+            let chr = *self.advance()?;
+            Self::push_literal(parts, chr);
+        }
+        if !self.match_char('\'') {
+            return Err(ScanError::UnterminatedQuote('\'', start));
+        }
+        Ok(())
+    }
+
+    // scans "$'...'", where escapes like "\n", "\t", "\x41" or "\u00e9" stand for characters
+    fn ansi_c_quoted(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        let start = self.curr;
+        self.curr += 2;
+        loop {
+            if self.is_end() {
+                return Err(ScanError::UnterminatedQuote('\'', start));
+            }
+            let chr = *self.advance()?;
+            match chr {
+                '\'' => return Ok(()),
+                '\\' if !self.is_end() => self.ansi_c_escape(parts)?,
+                chr => Self::push_literal(parts, chr),
+            }
+        }
+    }
+
+    // an unknown escape is kept as it is, like the "\q" in "$'\q'"
+    fn ansi_c_escape(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        let chr = *self.advance()?;
+        let escaped = match chr {
+            'a' => Some('\x07'),
+            'b' => Some('\x08'),
+            'e' | 'E' => Some('\x1b'),
+            'f' => Some('\x0c'),
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            'v' => Some('\x0b'),
+            '\\' | '\'' | '"' | '?' => Some(chr),
+            '0'..='7' => {
+                self.curr -= 1;
+                self.escaped_number(8, 3)
+            }
+            'x' => self.escaped_number(16, 2),
+            'u' => self.escaped_number(16, 4),
+            'U' => self.escaped_number(16, 8),
+            // "\cx" is control-x
+            'c' if !self.is_end() => {
+                let control = *self.advance()?;
+                char::from_u32(control.to_ascii_uppercase() as u32 ^ 0x40)
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(escaped) => Self::push_literal(parts, escaped),
+            None => {
+                Self::push_literal(parts, '\\');
+                Self::push_literal(parts, chr);
+            }
+        }
+        Ok(())
+    }
+
+    // reads up to the given number of digits, None if there are none
+    fn escaped_number(&mut self, radix: u32, max_digits: usize) -> Option<char> {
+        let mut value = 0;
+        let mut digits = 0;
+        while digits < max_digits {
+            let Some(digit) = self
+                .chars
+                .get(self.curr)
+                .and_then(|chr| chr.to_digit(radix))
+            else {
+                break;
+            };
+            value = value * radix + digit;
+            digits += 1;
+            self.curr += 1;
+        }
+        if digits == 0 {
+            return None;
+        }
+        char::from_u32(value).or(Some(char::REPLACEMENT_CHARACTER))
+    }
+
+    // scans a word, which can be made of unquoted, quoted and escaped parts like "a'b'\ c".
+    // Words that are quoted in any way can't be assignments, redirect numbers or reserved
+    // words.
+    fn regular_token(&mut self, assignment_value: bool) -> Result<Option<Token>, ScanError> {
+        let mut parts = vec![];
+        let mut quoted = false;
+        while !(self.is_end() || self.peek()?.is_whitespace()) {
+            let chr = *self.peek()?;
+            match chr {
+                '\'' => {
+                    self.advance()?;
+                    self.single_quoted(&mut parts)?;
+                    quoted = true;
+                }
+                '"' => {
+                    self.advance()?;
+                    let inner = self.double_quoted()?;
+                    // text without expansions joins the unquoted text around it
+                    match inner.as_slice() {
+                        [] => (),
+                        [WordPart::Literal(literal)] => literal
+                            .chars()
+                            .for_each(|chr| Self::push_literal(&mut parts, chr)),
+                        _ => parts.push(WordPart::DoubleQuoted(inner)),
+                    }
+                    quoted = true;
+                }
+                '\\' if self.line_continuation()? => (),
+                '\\' => {
+                    self.advance()?;
+                    let chr = *self.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                    quoted = true;
+                }
+                '$' if self.chars.get(self.curr + 1) == Some(&'\'') => {
+                    self.ansi_c_quoted(&mut parts)?;
+                    quoted = true;
+                }
+                '$' => self.expansion(&mut parts)?,
+                '`' => self.backquote(&mut parts)?,
+                '*' | '?' => {
+                    self.advance()?;
+                    Self::push_glob(&mut parts, &chr.to_string());
+                }
+                '[' => match parts.as_slice() {
+                    // an array element, as in "arr[1]=x" or "map[key]+=x"
+                    [WordPart::Literal(name)]
+                        if !assignment_value && !quoted && is_valid_name(name) =>
+                    {
+                        let Some((subscript, append)) = self.element_subscript(true) else {
+                            self.bracket(&mut parts)?;
+                            continue;
+                        };
+                        let name = format!("{}[{}]", name, subscript);
+                        self.tokens.push(if append {
+                            Token::AppendAssignment(name)
+                        } else {
+                            Token::Assignment(name)
+                        });
+                        return self.assignment_value();
+                    }
+                    _ => self.bracket(&mut parts)?,
+                },
+                '~' if parts.is_empty()
+                    || (assignment_value
+                        && matches!(parts.last(), Some(WordPart::Literal(literal)) if literal.ends_with(':'))) =>
+                {
+                    self.tilde(&mut parts, if assignment_value { ":" } else { "" })?
+                }
+                // values of assignments aren't brace expanded
+                '{' if !assignment_value => {
+                    let word_start = parts.is_empty();
+                    self.brace(&mut parts, word_start)?
+                }
+                ']' | '~' | '{' | '}' | '#' => {
+                    self.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                }
+                '=' => {
+                    self.advance()?;
+                    match parts.as_slice() {
+                        [WordPart::Literal(name)]
+                            if !assignment_value && !quoted && is_valid_name(name) =>
+                        {
+                            // the value follows the assignment as its own token
+                            self.tokens.push(Token::Assignment(name.clone()));
+                            return self.assignment_value();
+                        }
+                        [WordPart::Literal(name)]
+                            if !assignment_value
+                                && !quoted
+                                && name.strip_suffix('+').is_some_and(is_valid_name) =>
+                        {
+                            let name = name.trim_end_matches('+').to_string();
+                            self.tokens.push(Token::AppendAssignment(name));
+                            return self.assignment_value();
+                        }
+                        _ => Self::push_literal(&mut parts, chr),
+                    }
+                }
+                _ if SPECIAL_CHARACTERS.contains(&chr) => break,
+                _ => {
+                    self.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                }
+            }
+        }
+        match parts.as_slice() {
+            [] if assignment_value || quoted => Ok(Some(Token::Regular(String::new()))),
+            [] => Err(ScanError::EmptyToken(
+                "Regular token is empty. Current character: {:?}",
+            )),
+            // a number right before a redirect is the file descriptor it applies to
+            [WordPart::Literal(token)]
+                if !assignment_value
+                    && !quoted
+                    && !self.is_end()
+                    && matches!(self.peek()?, '<' | '>')
+                    && token.chars().all(|chr| chr.is_ascii_digit()) =>
+            {
+                match token.parse::<i32>() {
+                    Ok(fd) => Ok(Some(Token::IoNumber(fd))),
+                    Err(_) => Ok(Some(Token::Regular(token.clone()))),
+                }
+            }
+            // a quoted reserved word is an ordinary word, as in "echo; 'if'"
+            [WordPart::Literal(token)] if !(quoted && RESERVED_WORDS.contains(&token.as_str())) => {
+                Ok(Some(Token::Regular(token.clone())))
+            }
+            _ => Ok(Some(Token::Expansion(parts))),
+        }
+    }
+
+    // the value of "NAME=value" is always scanned, even if it is empty
+    fn assignment_value(&mut self) -> Result<Option<Token>, ScanError> {
+        if !self.is_end() && *self.peek()? == '(' {
+            return self.array();
+        }
+        self.regular_token(true)
+    }
+
+    // scans the elements of "NAME=(a b [5]=c)", which can span lines, between the
+    // parentheses. If the input ends before the ")", the parser asks for more.
+    fn array(&mut self) -> Result<Option<Token>, ScanError> {
+        self.advance()?;
+        self.tokens.push(Token::SubshellStart);
+        loop {
+            while !self.is_end() && self.peek()?.is_whitespace() {
+                self.advance()?;
+            }
+            if self.is_end() {
+                return Ok(None);
+            }
+            if self.chars[self.curr] == '#' {
+                self.comment();
+                continue;
+            }
+            let token = match *self.peek()? {
+                ')' => {
+                    self.advance()?;
+                    return Ok(Some(Token::SubshellEnd));
+                }
+                '[' => match self.element_subscript(false) {
+                    Some((subscript, _)) => {
+                        self.tokens.push(Token::Subscript(subscript));
+                        self.regular_token(true)?
+                    }
+                    None => self.regular_token(false)?,
+                },
+                _ => self.regular_token(false)?,
+            };
+            self.tokens.extend(token);
+        }
+    }
+
+    // scans the "[subscript]=" or "[subscript]+=" of an array element at a "[", and returns
+    // the subscript and whether it appends. Otherwise nothing is consumed.
+    fn element_subscript(&mut self, append: bool) -> Option<(String, bool)> {
+        let mut depth = 0;
+        for idx in self.curr..self.chars.len() {
+            match self.chars[idx] {
+                '[' => depth += 1,
+                ']' if depth > 1 => depth -= 1,
+                ']' => {
+                    let subscript = self.chars[self.curr + 1..idx].iter().collect();
+                    let rest = &self.chars[idx + 1..];
+                    let appends = append && rest.starts_with(&['+', '=']);
+                    if !(appends || rest.starts_with(&['='])) {
+                        return None;
+                    }
+                    self.curr = idx + if appends { 3 } else { 2 };
+                    return Some((subscript, appends));
+                }
+                chr if chr.is_whitespace() => return None,
+                _ => (),
+            }
+        }
+        None
+    }
+
+    // scans "$NAME", "${NAME}" or a special parameter like "$?", a "$" that isn't followed by a name is kept literally
+    fn expansion(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        self.advance()?;
+        if !self.is_end() && *self.peek()? == '(' {
+            if let Some(expression) = self.arithmetic_command() {
+                parts.push(WordPart::Arithmetic(expression));
+                return Ok(());
+            }
+        }
+        if self.match_char('(') {
+            return self.command_substitution(parts);
+        }
+        if self.match_char('{') {
+            return self.parameter(parts);
+        }
+        let name = self.variable_name(false)?;
+        if name.is_empty() {
+            Self::push_literal(parts, '$');
+        } else {
+            parts.push(WordPart::Variable(name));
+        }
+        Ok(())
+    }
+
+    // scans the rest of "${name}", or of one with an operator like "${name:-default}"
+    fn parameter(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        let bad_substitution =
+            || ScanError::BadSubstitution("Expected a variable name and operator inside \"${}\"");
+        // "${#}" is the number of positional parameters, "${#name}" the length of the value
+        let length = self.chars.get(self.curr) == Some(&'#')
+            && self.chars.get(self.curr + 1).is_some_and(|chr| *chr != '}');
+        // "${!arr[@]}" are the indexes or keys of an array
+        let keys = self.chars.get(self.curr) == Some(&'!')
+            && self.chars.get(self.curr + 1).is_some_and(|chr| *chr != '}');
+        if length || keys {
+            self.advance()?;
+        }
+        let mut name = self.variable_name(true)?;
+        if name.is_empty() || self.is_end() {
+            return Err(bad_substitution());
+        }
+        // an array element, as in "${arr[1]}" or "${arr[@]}"
+        if is_valid_name(&name) && self.match_char('[') {
+            let subscript = self.parameter_text(&[']'])?;
+            if !self.match_char(']') || subscript.is_empty() {
+                return Err(bad_substitution());
+            }
+            name = format!("{}[{}]", name, subscript);
+        }
+        if length || keys {
+            if !self.match_char('}')
+                || (keys && !matches!(split_subscript(&name), Some((_, "@" | "*"))))
+            {
+                return Err(bad_substitution());
+            }
+            let operator = if keys {
+                ParameterOperator::Keys
+            } else {
+                ParameterOperator::Length
+            };
+            parts.push(WordPart::Parameter(name, operator));
+            return Ok(());
+        }
+        let chr = *self.advance()?;
+        let operator = match chr {
+            '}' => {
+                parts.push(WordPart::Variable(name));
+                return Ok(());
+            }
+            ':' if !self.is_end() && "-=?+".contains(*self.peek()?) => {
+                let operator = *self.advance()?;
+                Self::test_operator(operator, true, self.parameter_word(&[])?)
+            }
+            ':' => {
+                let offset = self.parameter_text(&[':'])?;
+                let length = if self.match_char(':') {
+                    Some(self.parameter_text(&[])?)
+                } else {
+                    None
+                };
+                ParameterOperator::Substring(offset, length)
+            }
+            chr @ ('-' | '=' | '?' | '+') => {
+                Self::test_operator(chr, false, self.parameter_word(&[])?)
+            }
+            '#' => {
+                let longest = self.match_char('#');
+                ParameterOperator::RemovePrefix(longest, self.parameter_word(&[])?)
+            }
+            '%' => {
+                let longest = self.match_char('%');
+                ParameterOperator::RemoveSuffix(longest, self.parameter_word(&[])?)
+            }
+            '/' => {
+                let mode = if self.match_char('/') {
+                    ReplaceMode::All
+                } else if self.match_char('#') {
+                    ReplaceMode::Prefix
+                } else if self.match_char('%') {
+                    ReplaceMode::Suffix
+                } else {
+                    ReplaceMode::First
+                };
+                let pattern = self.parameter_word(&['/'])?;
+                let replacement = if self.match_char('/') {
+                    self.parameter_word(&[])?
+                } else {
+                    vec![]
+                };
+                ParameterOperator::Replace(mode, pattern, replacement)
+            }
+            chr @ ('^' | ',') => ParameterOperator::Case(chr == '^', self.match_char(chr)),
+            _ => return Err(bad_substitution()),
+        };
+        if !self.match_char('}') {
+            return Err(bad_substitution());
         }
+        parts.push(WordPart::Parameter(name, operator));
+        Ok(())
     }
 
-    fn quoted_token(&mut self) -> Result<Option<Token>, ScanError> {
-        let mut token = String::new();
-        let quote = *self.advance()?;
-        while !(self.is_end() || *self.peek()? == quote) {
-            token.push(*self.advance()?);
+    fn test_operator(operator: char, test_empty: bool, word: Vec<WordPart>) -> ParameterOperator {
+        match operator {
+            '-' => ParameterOperator::Default(test_empty, word),
+            '=' => ParameterOperator::Assign(test_empty, word),
+            '?' => ParameterOperator::Error(test_empty, word),
+            _ => ParameterOperator::Alternative(test_empty, word),
         }
+    }
+
+    // scans the word after a parameter operator up to the closing "}" or one of the ends,
+    // which aren't consumed. Quoted characters are literal, and unquoted glob characters
+    // stay special for the operators that take patterns.
+    fn parameter_word(&mut self, ends: &[char]) -> Result<Vec<WordPart>, ScanError> {
+        let mut parts = vec![];
+        // braces inside the word, as in "${x:-{a,b}}"
+        let mut depth = 0;
+        loop {
+            if self.is_end() {
+                return Err(ScanError::BadSubstitution("Expected \"}\" after \"${\""));
+            }
+            let chr = *self.peek()?;
+            match chr {
+                '}' if depth == 0 => break,
+                _ if depth == 0 && ends.contains(&chr) => break,
+                '$' => self.expansion(&mut parts)?,
+                '`' => self.backquote(&mut parts)?,
+                '*' | '?' => {
+                    self.advance()?;
+                    Self::push_glob(&mut parts, &chr.to_string());
+                }
+                '[' => self.bracket(&mut parts)?,
+                '"' => {
+                    self.advance()?;
+                    parts.push(WordPart::DoubleQuoted(self.double_quoted()?));
+                }
+                '\'' => {
+                    self.advance()?;
+                    self.single_quoted(&mut parts)?;
+                }
+                '\\' => {
+                    self.advance()?;
+                    let chr = *self.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                }
+                _ => {
+                    match chr {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    self.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                }
+            }
+        }
+        Ok(parts)
+    }
+
+    // scans the arithmetic expressions of "${name:offset:length}" as they are, up to the
+    // closing "}" or one of the ends
+    fn parameter_text(&mut self, ends: &[char]) -> Result<String, ScanError> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            if self.is_end() {
+                return Err(ScanError::BadSubstitution("Expected \"}\" after \"${\""));
+            }
+            let chr = *self.peek()?;
+            match chr {
+                '}' if depth == 0 => break,
+                _ if depth == 0 && ends.contains(&chr) => break,
+                '{' | '(' => depth += 1,
+                '}' | ')' => depth -= 1,
+                _ => (),
+            }
+            text.push(*self.advance()?);
+        }
+        Ok(text)
+    }
+
+    // positional parameters after the ninth need braces, as in "${10}"
+    fn variable_name(&mut self, braced: bool) -> Result<String, ScanError> {
+        let mut name = String::new();
+        if braced && !self.is_end() && self.peek()?.is_ascii_digit() {
+            while !self.is_end() && self.peek()?.is_ascii_digit() {
+                name.push(*self.advance()?);
+            }
+            return Ok(name);
+        }
+        if !self.is_end() && SPECIAL_PARAMETERS.contains(self.peek()?) {
+            name.push(*self.advance()?);
+            return Ok(name);
+        }
+        while !self.is_end() {
+            let chr = *self.peek()?;
+            if !(chr == '_'
+                || chr.is_ascii_alphabetic()
+                || (!name.is_empty() && chr.is_ascii_digit()))
+            {
+                break;
+            }
+            name.push(*self.advance()?);
+        }
+        Ok(name)
+    }
+
+    // scans the command of "$(...)" with a nested scanner that stops at the closing parenthesis,
+    // skipping the ones that close subshells inside it
+    fn command_substitution(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        let mut scanner = Scanner::new(String::new());
+        scanner.chars = self.chars[self.curr..].to_vec();
+        let mut depth = 0;
+        loop {
+            if scanner.is_end() {
+                return Err(ScanError::BadSubstitution("Expected \")\" after \"$(\""));
+            }
+            if depth == 0 && scanner.match_char(')') {
+                break;
+            }
+            if let Some(token) = scanner.scan_token()? {
+                match token {
+                    Token::SubshellStart => depth += 1,
+                    Token::SubshellEnd => depth -= 1,
+                    _ => (),
+                }
+                scanner.tokens.push(token);
+            }
+        }
+        self.curr += scanner.curr;
+        scanner.tokens.push(Token::EOF);
+        parts.push(Self::parse_substitution(scanner.tokens)?);
+        Ok(())
+    }
+
+    // scans "`...`", where a backslash escapes "`", "$" and itself
+    fn backquote(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
         self.advance()?;
-        Ok(Some(Token::Regular(token)))
+        let mut command = String::new();
+        loop {
+            if self.is_end() {
+                return Err(ScanError::BadSubstitution("Expected closing \"`\""));
+            }
+            let chr = *self.advance()?;
+            match chr {
+                '`' => break,
+                '\\' if !self.is_end() && "`$\\".contains(*self.peek()?) => {
+                    command.push(*self.advance()?)
+                }
+                chr => command.push(chr),
+            }
+        }
+        let tokens = Scanner::new(command).scan_tokens()?;
+        parts.push(Self::parse_substitution(tokens)?);
+        Ok(())
     }
 
-    fn regular_token(&mut self) -> Result<Option<Token>, ScanError> {
-        let mut token = String::new();
-        while !(self.is_end()
-            || SPECIAL_CHARACTERS.contains(self.peek()?)
-            || self.peek()?.is_whitespace())
+    fn parse_substitution(tokens: Vec<Token>) -> Result<WordPart, ScanError> {
+        let parser = Parser::new(tokens);
+        let node = if parser.is_empty() {
+            Node::CommandSequence(vec![])
+        } else {
+            parser.parse().map_err(|_| {
+                ScanError::BadSubstitution("Failed parsing the command of a substitution")
+            })?
+        };
+        Ok(WordPart::CommandSubstitution(Box::new(node)))
+    }
+
+    // scans the delimiter after "<<" or "<<-". The body is read once the line ends.
+    fn heredoc(&mut self) -> Result<Option<Token>, ScanError> {
+        let strip_tabs = self.match_char('-');
+        while self.match_char(' ') || self.match_char('\t') {}
+        // quoting any part of the delimiter turns off expansions in the body
+        let mut delimiter = String::new();
+        let mut expand = true;
+        while !(self.is_end() || self.peek()?.is_whitespace() || "<>|&;()".contains(*self.peek()?))
         {
-            token.push(*self.advance()?);
+            match *self.advance()? {
+                quote @ ('\'' | '"') => {
+                    expand = false;
+                    while !(self.is_end() || *self.peek()? == quote) {
+                        delimiter.push(*self.advance()?);
+                    }
+                    self.advance()?;
+                }
+                '\\' => {
+                    expand = false;
+                    delimiter.push(*self.advance()?);
+                }
+                chr => delimiter.push(chr),
+            }
         }
-        if token.is_empty() {
-            return Err(ScanError::EmptyToken(
-                "Regular token is empty. Current character: {:?}",
-            ));
+        if delimiter.is_empty() {
+            return Err(ScanError::EmptyToken("Expected heredoc delimiter"));
+        }
+        self.tokens.push(Token::HereDoc(delimiter.clone(), vec![]));
+        self.pending_heredocs.push(PendingHereDoc {
+            token: self.tokens.len() - 1,
+            delimiter,
+            strip_tabs,
+            expand,
+        });
+        Ok(None)
+    }
+
+    // reads the bodies of the heredocs started on the line that just ended, in order
+    fn heredoc_bodies(&mut self) -> Result<(), ScanError> {
+        for heredoc in std::mem::take(&mut self.pending_heredocs) {
+            let mut parts = vec![];
+            loop {
+                if self.is_end() {
+                    return Err(ScanError::UnterminatedHereDoc(heredoc.delimiter));
+                }
+                while heredoc.strip_tabs && self.match_char('\t') {}
+                let line_end = self.chars[self.curr..]
+                    .iter()
+                    .position(|chr| *chr == '\n')
+                    .map_or(self.chars.len(), |len| self.curr + len);
+                if self.chars[self.curr..line_end]
+                    == heredoc.delimiter.chars().collect::<Vec<_>>()[..]
+                {
+                    self.curr = (line_end + 1).min(self.chars.len());
+                    break;
+                }
+                while self.curr < line_end {
+                    let chr = *self.peek()?;
+                    match chr {
+                        '$' if heredoc.expand => self.expansion(&mut parts)?,
+                        '`' if heredoc.expand => self.backquote(&mut parts)?,
+                        '\\' if heredoc.expand => {
+                            self.advance()?;
+                            match self.peek() {
+                                Ok('$' | '\\' | '`') => {
+                                    Self::push_literal(&mut parts, *self.advance()?)
+                                }
+                                _ => Self::push_literal(&mut parts, chr),
+                            }
+                        }
+                        _ => {
+                            self.advance()?;
+                            Self::push_literal(&mut parts, chr);
+                        }
+                    }
+                }
+                // a body line without a newline has to be followed by the delimiter
+                if !self.match_char('\n') {
+                    return Err(ScanError::UnterminatedHereDoc(heredoc.delimiter));
+                }
+                Self::push_literal(&mut parts, '\n');
+            }
+            self.tokens[heredoc.token] = Token::HereDoc(heredoc.delimiter, parts);
+        }
+        Ok(())
+    }
+
+    // scans "((expression))", also after the "$" of "$((expression))". Without a matching "))"
+    // it's the start of two subshells instead, as in "((cd src; make); ls)".
+    fn arithmetic_command(&mut self) -> Option<String> {
+        if self.chars.get(self.curr + 1) != Some(&'(') {
+            return None;
+        }
+        let start = self.curr + 2;
+        let mut depth = 0;
+        for idx in start..self.chars.len() {
+            match self.chars[idx] {
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ')' if self.chars.get(idx + 1) == Some(&')') => {
+                    self.curr = idx + 2;
+                    return Some(self.chars[start..idx].iter().collect());
+                }
+                ')' => return None,
+                _ => (),
+            }
+        }
+        None
+    }
+
+    // scans the expansions in an arithmetic expression, which are expanded before it's
+    // evaluated. Everything else is literal.
+    pub fn arithmetic_parts(expression: &str) -> Result<Vec<WordPart>, ScanError> {
+        let mut scanner = Scanner::new(expression.to_string());
+        let mut parts = vec![];
+        while !scanner.is_end() {
+            match *scanner.peek()? {
+                '$' => scanner.expansion(&mut parts)?,
+                '`' => scanner.backquote(&mut parts)?,
+                chr => {
+                    scanner.advance()?;
+                    Self::push_literal(&mut parts, chr);
+                }
+            }
+        }
+        Ok(parts)
+    }
+
+    // scans "~", "~user", "~+" or "~-" up to the next "/" or one of the terminators, like the
+    // ":" in "PATH=~/bin:~/.cargo/bin". If anything else follows, the "~" is an ordinary character.
+    fn tilde(&mut self, parts: &mut Vec<WordPart>, terminators: &str) -> Result<(), ScanError> {
+        self.advance()?;
+        let mut name = String::new();
+        while !self.is_end() && (self.peek()?.is_alphanumeric() || "_-.+".contains(*self.peek()?)) {
+            name.push(*self.advance()?);
+        }
+        let ends_prefix = self.is_end() || {
+            let chr = *self.peek()?;
+            chr == '/'
+                || chr.is_whitespace()
+                || terminators.contains(chr)
+                || ";|&<>()".contains(chr)
+        };
+        if ends_prefix {
+            parts.push(WordPart::Tilde(name));
+        } else {
+            Self::push_literal(parts, '~');
+            name.chars().for_each(|chr| Self::push_literal(parts, chr));
+        }
+        Ok(())
+    }
+
+    // scans a bracket expression like "[a-z]" or "[![:space:]]" as a glob. Without a
+    // closing "]" in the same word, the "[" is an ordinary character, as in "[ -f file ]".
+    fn bracket(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ScanError> {
+        let start = self.curr;
+        let mut idx = start + 1;
+        let chars = &self.chars;
+        if matches!(chars.get(idx), Some('!' | '^')) {
+            idx += 1;
+        }
+        // a "]" right at the start is part of the set
+        if chars.get(idx) == Some(&']') {
+            idx += 1;
+        }
+        while let Some(&chr) = chars.get(idx) {
+            if chr == ']' {
+                let glob: String = chars[start..=idx].iter().collect();
+                self.curr = idx + 1;
+                Self::push_glob(parts, &glob);
+                return Ok(());
+            }
+            if chr == '[' && chars.get(idx + 1) == Some(&':') {
+                // skips a character class like "[:alpha:]"
+                match chars[idx..].windows(2).position(|pair| pair == [':', ']']) {
+                    Some(len) => idx += len + 2,
+                    None => break,
+                }
+                continue;
+            }
+            if chr.is_whitespace() || "'\"$`;|&<>()".contains(chr) {
+                break;
+            }
+            idx += 1;
+        }
+        self.advance()?;
+        Self::push_literal(parts, '[');
+        Ok(())
+    }
+
+    // scans "{a,b}" or a sequence like "{1..10..2}". Without a "," or ".." or a closing "}"
+    // in the same word, the "{" is an ordinary character, as in "{ echo; }".
+    // An alternative at the start of the word may start with a tilde, as in "{~,/tmp}/file".
+    fn brace(&mut self, parts: &mut Vec<WordPart>, word_start: bool) -> Result<(), ScanError> {
+        let start = self.curr;
+        self.advance()?;
+        match self.brace_alternatives(word_start)? {
+            Some(alternatives) => parts.push(WordPart::Brace(alternatives)),
+            None => {
+                self.curr = start + 1;
+                Self::push_literal(parts, '{');
+            }
+        }
+        Ok(())
+    }
+
+    fn brace_alternatives(
+        &mut self,
+        word_start: bool,
+    ) -> Result<Option<Vec<Vec<WordPart>>>, ScanError> {
+        let mut alternatives = vec![];
+        let mut current = vec![];
+        while !self.is_end() {
+            let chr = *self.peek()?;
+            match chr {
+                ',' => {
+                    self.advance()?;
+                    alternatives.push(take(&mut current));
+                }
+                '}' => {
+                    self.advance()?;
+                    if !alternatives.is_empty() {
+                        alternatives.push(current);
+                        return Ok(Some(alternatives));
+                    }
+                    return Ok(match current.as_slice() {
+                        [WordPart::Literal(body)] => sequence(body).map(|words| {
+                            words
+                                .into_iter()
+                                .map(|word| vec![WordPart::Literal(word)])
+                                .collect()
+                        }),
+                        _ => None,
+                    });
+                }
+                '$' => self.expansion(&mut current)?,
+                '`' => self.backquote(&mut current)?,
+                '*' | '?' => {
+                    self.advance()?;
+                    Self::push_glob(&mut current, &chr.to_string());
+                }
+                '[' => self.bracket(&mut current)?,
+                '~' if word_start && current.is_empty() => self.tilde(&mut current, ",}")?,
+                '{' => {
+                    let word_start = word_start && current.is_empty();
+                    self.brace(&mut current, word_start)?
+                }
+                _ if chr.is_whitespace() || "'\"\\;|&<>()".contains(chr) => return Ok(None),
+                _ => {
+                    self.advance()?;
+                    Self::push_literal(&mut current, chr);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn push_glob(parts: &mut Vec<WordPart>, glob: &str) {
+        if let Some(WordPart::Glob(existing)) = parts.last_mut() {
+            existing.push_str(glob);
+        } else {
+            parts.push(WordPart::Glob(glob.to_string()));
+        }
+    }
+
+    fn push_literal(parts: &mut Vec<WordPart>, chr: char) {
+        if let Some(WordPart::Literal(literal)) = parts.last_mut() {
+            literal.push(chr);
+        } else {
+            parts.push(WordPart::Literal(chr.to_string()));
+        }
+    }
+
+    // skips a comment up to the newline that ends it
+    fn comment(&mut self) {
+        while !self.is_end() && self.chars[self.curr] != '\n' {
+            self.curr += 1;
+        }
+    }
+
+    // skips a backslash followed by a newline, which joins the lines. If the input ends
+    // there or with a backslash, the next line is missing.
+    fn line_continuation(&mut self) -> Result<bool, ScanError> {
+        match self.chars.get(self.curr + 1) {
+            None => Err(ScanError::LineContinuation),
+            Some('\n') if self.curr + 2 == self.chars.len() => Err(ScanError::LineContinuation),
+            Some('\n') => {
+                self.curr += 2;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
         }
-        Ok(Some(Token::Regular(token)))
     }
 
     fn whitespace(&mut self) -> Result<Option<Token>, ScanError> {
@@ -98,6 +1080,14 @@ impl Scanner {
         self.curr >= self.chars.len()
     }
 
+    fn match_char(&mut self, chr: char) -> bool {
+        if self.is_end() || self.chars[self.curr] != chr {
+            return false;
+        }
+        self.curr += 1;
+        true
+    }
+
     fn advance(&mut self) -> Result<&char, ScanError> {
         if self.is_end() {
             return Err(ScanError::IndexOutOfBounds("peek out of bounds"));
@@ -1,19 +1,38 @@
+use crate::ast::WordPart;
+
 // ignore warnings for variants that aren't implemented yet
 #[allow(dead_code)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Regular(String),
-    Expansion,
+    Expansion(Vec<WordPart>), // word containing $NAME or ${NAME}
     SingleQuote,
     DoubleQuote,
-    Assignment,
-    LRedirect, // <
-    RRedirect, // >
+    Assignment(String), // NAME= or NAME[subscript]= at the start of a word, followed by the value
+    AppendAssignment(String), // NAME+= or NAME[subscript]+=
+    Subscript(String),  // [subscript]= of an element in an array like "(a [5]=b)"
+    IoNumber(i32),      // file descriptor right before a redirect, as in 2>
+    LRedirect,          // <
+    RRedirect,          // >
+    RAppend,            // >>
+    ReadWrite,          // <>
+    LDuplicate,         // <&
+    RDuplicate,         // >&
+    RedirectAll,        // &>
+    AppendAll,          // &>>
+    HereDoc(String, Vec<WordPart>), // <<DELIM and its body, which is a literal if DELIM is quoted
+    HereString,         // <<<
     Pipe,
+    And, // &&
+    Or,  // ||
     CommandSeparator,
+    CaseBreak,       // ;;
+    CaseFallThrough, // ;&
+    CaseContinue,    // ;;&
+    Newline,         // ends a command like ";", and lets compound commands span lines
     SubshellStart,
     SubshellEnd,
-    Home,
+    Arithmetic(String), // ((expression))
     Background,
     EOF,
 }
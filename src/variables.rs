@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env::vars;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    // indexed arrays can have gaps, as after "arr[5]=x"
+    Indexed(BTreeMap<usize, String>),
+    // declared with "declare -A", keys are kept sorted
+    Associative(BTreeMap<String, String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    pub value: Value,
+    pub exported: bool,
+}
+
+// an element of an array, a negative index counts back from the end
+#[derive(Clone, Debug, PartialEq)]
+pub enum Subscript {
+    Index(i64),
+    Key(String),
+}
+
+impl Value {
+    // the string a scalar holds, an array stands for its element 0 like in bash
+    fn scalar(&self) -> Option<&str> {
+        self.element(&Subscript::Index(0))
+    }
+
+    fn element(&self, subscript: &Subscript) -> Option<&str> {
+        match (self, subscript) {
+            (Self::Scalar(value), Subscript::Index(0)) => Some(value),
+            (Self::Scalar(_), _) => None,
+            (Self::Indexed(elements), Subscript::Index(index)) => {
+                elements.get(&self.resolve(*index)?).map(String::as_str)
+            }
+            (Self::Indexed(_), Subscript::Key(_)) => None,
+            (Self::Associative(elements), Subscript::Key(key)) => {
+                elements.get(key).map(String::as_str)
+            }
+            (Self::Associative(elements), Subscript::Index(index)) => {
+                elements.get(&index.to_string()).map(String::as_str)
+            }
+        }
+    }
+
+    // negative indexes count back from the end of an indexed array
+    fn resolve(&self, index: i64) -> Option<usize> {
+        if index >= 0 {
+            return Some(index as usize);
+        }
+        let end = match self {
+            Self::Indexed(elements) => elements.keys().next_back().map_or(0, |last| last + 1),
+            _ => 1,
+        };
+        end.checked_sub(index.unsigned_abs() as usize)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Variables {
+    table: HashMap<String, Variable>,
+    // for each running function call, the variables it made local with the values they had
+    // before, which come back when it returns
+    scopes: Vec<HashMap<String, Option<Variable>>>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        let table = vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value: Value::Scalar(value),
+                    exported: true,
+                };
+                (name, variable)
+            })
+            .collect();
+        Self {
+            table,
+            scopes: vec![],
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.table.get(name)?.value.scalar()
+    }
+
+    // keeps the export flag of a variable that already exists. Setting an array sets its
+    // element 0.
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.table.get_mut(name) {
+            Some(Variable {
+                value: Value::Scalar(_),
+                ..
+            })
+            | None => self.set_value(name, Value::Scalar(value)),
+            Some(_) => {
+                self.set_element(name, Subscript::Index(0), value);
+            }
+        }
+    }
+
+    fn set_value(&mut self, name: &str, value: Value) {
+        match self.table.get_mut(name) {
+            Some(variable) => variable.value = value,
+            None => {
+                let variable = Variable {
+                    value,
+                    exported: false,
+                };
+                self.table.insert(name.to_string(), variable);
+            }
+        }
+    }
+
+    pub fn is_associative(&self, name: &str) -> bool {
+        matches!(
+            self.table.get(name),
+            Some(Variable {
+                value: Value::Associative(_),
+                ..
+            })
+        )
+    }
+
+    // "declare -a" and "declare -A". A scalar becomes element 0 of an indexed array, an
+    // array of the other kind is an error.
+    pub fn declare_array(&mut self, name: &str, associative: bool) -> Result<(), &'static str> {
+        let value = match self.table.get(name).map(|variable| &variable.value) {
+            None => None,
+            Some(Value::Scalar(value)) if !associative => {
+                Some(Value::Indexed(BTreeMap::from([(0, value.clone())])))
+            }
+            Some(Value::Scalar(_)) => Some(Value::Associative(BTreeMap::new())),
+            Some(Value::Indexed(_)) if !associative => return Ok(()),
+            Some(Value::Associative(_)) if associative => return Ok(()),
+            Some(Value::Indexed(_)) => return Err("cannot convert indexed to associative array"),
+            Some(_) => return Err("cannot convert associative to indexed array"),
+        };
+        let value = value.unwrap_or_else(|| {
+            if associative {
+                Value::Associative(BTreeMap::new())
+            } else {
+                Value::Indexed(BTreeMap::new())
+            }
+        });
+        self.set_value(name, value);
+        Ok(())
+    }
+
+    // empties an array for "name=(...)", which makes an indexed array of anything but an
+    // associative one
+    pub fn clear_array(&mut self, name: &str) {
+        let value = if self.is_associative(name) {
+            Value::Associative(BTreeMap::new())
+        } else {
+            Value::Indexed(BTreeMap::new())
+        };
+        self.set_value(name, value);
+    }
+
+    pub fn get_element(&self, name: &str, subscript: &Subscript) -> Option<&str> {
+        self.table.get(name)?.value.element(subscript)
+    }
+
+    // sets an element, making an indexed array of a variable that isn't one. Returns false
+    // if a negative index is before the start of the array.
+    pub fn set_element(&mut self, name: &str, subscript: Subscript, value: String) -> bool {
+        if !matches!(
+            self.table.get(name).map(|variable| &variable.value),
+            Some(Value::Indexed(_) | Value::Associative(_))
+        ) {
+            let _ = self.declare_array(name, false);
+        }
+        let variable = self.table.get_mut(name).unwrap();
+        let index = match &subscript {
+            Subscript::Index(index) => variable.value.resolve(*index),
+            Subscript::Key(_) => None,
+        };
+        match (&mut variable.value, subscript) {
+            (Value::Indexed(elements), Subscript::Index(_)) => match index {
+                Some(index) => elements.insert(index, value),
+                None => return false,
+            },
+            (Value::Associative(elements), Subscript::Key(key)) => elements.insert(key, value),
+            (Value::Associative(elements), Subscript::Index(index)) => {
+                elements.insert(index.to_string(), value)
+            }
+            _ => return false,
+        };
+        true
+    }
+
+    pub fn unset_element(&mut self, name: &str, subscript: &Subscript) {
+        let Some(variable) = self.table.get_mut(name) else {
+            return;
+        };
+        let index = match subscript {
+            Subscript::Index(index) => variable.value.resolve(*index),
+            Subscript::Key(_) => None,
+        };
+        match (&mut variable.value, subscript) {
+            (Value::Scalar(_), Subscript::Index(0)) => {
+                self.table.remove(name);
+            }
+            (Value::Indexed(elements), Subscript::Index(_)) => {
+                index.map(|index| elements.remove(&index));
+            }
+            (Value::Associative(elements), Subscript::Key(key)) => {
+                elements.remove(key);
+            }
+            _ => (),
+        }
+    }
+
+    // the values of all elements in order, a scalar is an array with one element
+    pub fn values(&self, name: &str) -> Vec<String> {
+        match self.table.get(name).map(|variable| &variable.value) {
+            None => vec![],
+            Some(Value::Scalar(value)) => vec![value.clone()],
+            Some(Value::Indexed(elements)) => elements.values().cloned().collect(),
+            Some(Value::Associative(elements)) => elements.values().cloned().collect(),
+        }
+    }
+
+    // the indexes or keys of all elements, as for "${!arr[@]}"
+    pub fn keys(&self, name: &str) -> Vec<String> {
+        match self.table.get(name).map(|variable| &variable.value) {
+            None => vec![],
+            Some(Value::Scalar(_)) => vec!["0".to_string()],
+            Some(Value::Indexed(elements)) => elements.keys().map(usize::to_string).collect(),
+            Some(Value::Associative(elements)) => elements.keys().cloned().collect(),
+        }
+    }
+
+    // the index after the last element of an indexed array, where "arr+=(x)" appends. None if
+    // the last element already has the largest index.
+    pub fn next_index(&self, name: &str) -> Option<i64> {
+        match self.table.get(name).map(|variable| &variable.value) {
+            None => Some(0),
+            Some(Value::Indexed(elements)) => match elements.keys().next_back() {
+                Some(last) => i64::try_from(*last).ok()?.checked_add(1),
+                None => Some(0),
+            },
+            Some(_) => Some(1),
+        }
+    }
+
+    // returns false if the variable doesn't exist
+    pub fn export(&mut self, name: &str) -> bool {
+        match self.table.get_mut(name) {
+            Some(variable) => {
+                variable.exported = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.table.remove(name);
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, saved) in scope {
+            match saved {
+                Some(variable) => self.table.insert(name, variable),
+                None => self.table.remove(&name),
+            };
+        }
+    }
+
+    // makes a variable local to the innermost function call, so it's only visible there and
+    // in the functions it calls. Without a value it starts out unset, unless it's already
+    // local. Returns false outside of functions.
+    pub fn local(&mut self, name: &str, value: Option<String>) -> bool {
+        let Some(scope) = self.scopes.last_mut() else {
+            return false;
+        };
+        let shadowed = !scope.contains_key(name);
+        if shadowed {
+            scope.insert(name.to_string(), self.table.get(name).cloned());
+        }
+        match value {
+            Some(value) => {
+                let variable = Variable {
+                    value: Value::Scalar(value),
+                    exported: false,
+                };
+                self.table.insert(name.to_string(), variable);
+            }
+            // "local x" keeps the value of an x that is already local
+            None if shadowed => {
+                self.table.remove(name);
+            }
+            None => (),
+        }
+        true
+    }
+
+    // exported variables sorted by name, arrays can't be exported
+    pub fn exported(&self) -> Vec<(&str, &str)> {
+        let mut exported: Vec<(&str, &str)> = self
+            .table
+            .iter()
+            .filter(|(_, variable)| variable.exported)
+            .filter_map(|(name, variable)| match &variable.value {
+                Value::Scalar(value) => Some((name.as_str(), value.as_str())),
+                _ => None,
+            })
+            .collect();
+        exported.sort();
+        exported
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first == '_' || first.is_ascii_alphabetic() => {
+            chars.all(|chr| chr == '_' || chr.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+// splits an array element like "arr[1]" into the name and the subscript
+pub fn split_subscript(name: &str) -> Option<(&str, &str)> {
+    let (name, subscript) = name.split_once('[')?;
+    Some((name, subscript.strip_suffix(']')?))
+}
@@ -0,0 +1,118 @@
+mod utils;
+
+#[cfg(test)]
+mod test_arith {
+    use crate::crsh;
+    use crsh::arith::{evaluate, ArithEnv, ArithError};
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Env(HashMap<String, String>);
+
+    impl ArithEnv for Env {
+        fn variable(&self, name: &str) -> Option<String> {
+            self.0.get(name).cloned()
+        }
+
+        fn set_variable(&mut self, name: &str, value: i64) {
+            self.0.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    #[test]
+    fn evaluate_operators() {
+        let mut env = Env::default();
+        let cases = [
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("2 ** 3 ** 2", 512),
+            ("-7 / 2", -3),
+            ("7 % -3", 1),
+            ("1 << 4 | 1", 17),
+            ("!0 && 3 > 2", 1),
+            ("0 || 0", 0),
+            ("5 ? 1 : 2", 1),
+            ("~0", -1),
+            ("0x1f + 010 + 2#101", 44),
+            ("", 0),
+        ];
+        for (expression, value) in cases {
+            assert_eq!(Ok(value), evaluate(expression, &mut env), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn evaluate_variables() {
+        let mut env = Env::default();
+        env.set_variable("a", 3);
+        env.0.insert("b".into(), "a * 2".into());
+        assert_eq!(Ok(6), evaluate("b", &mut env));
+        assert_eq!(Ok(3), evaluate("a++", &mut env));
+        assert_eq!(Ok(5), evaluate("++a", &mut env));
+        assert_eq!(Ok(15), evaluate("c = a *= 3, c", &mut env));
+        assert_eq!(Some("15".into()), env.variable("c"));
+        assert_eq!(Ok(0), evaluate("unset", &mut env));
+        // the right side of "&&" isn't evaluated if the left is false
+        assert_eq!(Ok(0), evaluate("0 && (d = 1)", &mut env));
+        assert_eq!(None, env.variable("d"));
+    }
+
+    #[test]
+    fn evaluate_errors() {
+        let mut env = Env::default();
+        assert_eq!(
+            Err(ArithError("division by 0".into())),
+            evaluate("1 / 0", &mut env)
+        );
+        assert!(evaluate("1 +", &mut env).is_err());
+        assert!(evaluate("(1", &mut env).is_err());
+        assert!(evaluate("3 = 4", &mut env).is_err());
+        env.0.insert("x".into(), "x".into());
+        assert!(evaluate("x", &mut env).is_err());
+    }
+
+    #[test]
+    fn evaluate_overflow() {
+        let mut env = Env::default();
+        let overflow = Err(ArithError("integer overflow".into()));
+        assert_eq!(overflow, evaluate("9223372036854775807 + 1", &mut env));
+        assert_eq!(overflow, evaluate("-9223372036854775807 - 2", &mut env));
+        assert_eq!(overflow, evaluate("2 ** 63", &mut env));
+        assert_eq!(overflow, evaluate("99999999999999999999", &mut env));
+        assert_eq!(
+            overflow,
+            evaluate("(-9223372036854775807 - 1) / -1", &mut env)
+        );
+        assert!(evaluate("1 << 64", &mut env).is_err());
+        assert_eq!(Ok(i64::MAX), evaluate("0x7fffffffffffffff", &mut env));
+    }
+
+    #[test]
+    fn evaluate_nesting() {
+        let mut env = Env::default();
+        let exceeded = Err(ArithError("expression recursion level exceeded".into()));
+        let parens = format!("{}1{}", "(".repeat(5000), ")".repeat(5000));
+        assert_eq!(exceeded, evaluate(&parens, &mut env));
+        assert_eq!(exceeded, evaluate(&"- ".repeat(5000), &mut env));
+        assert_eq!(exceeded, evaluate(&"1 ** ".repeat(5000), &mut env));
+        env.0.insert("a".into(), "b".into());
+        env.0.insert("b".into(), "(a)".into());
+        assert_eq!(exceeded, evaluate("a", &mut env));
+        // operators of the same precedence don't nest
+        let sum = vec!["1"; 100000].join(" + ");
+        assert_eq!(Ok(100000), evaluate(&sum, &mut env));
+        let parens = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        assert_eq!(Ok(1), evaluate(&parens, &mut env));
+    }
+
+    #[test]
+    fn expansion_error_status() {
+        // the error is printed by the expansion, the command only fails
+        for command in ["echo $((2**64))", "echo $((1?2))"] {
+            let output = crsh!("-c", command);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!stderr.contains("Execution error"), "{}", stderr);
+            assert_eq!(Some(1), output.status.code());
+        }
+    }
+}
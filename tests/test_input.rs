@@ -0,0 +1,32 @@
+mod utils;
+
+#[cfg(test)]
+mod tests {
+    use crate::crsh;
+
+    #[test]
+    fn piped_script() {
+        // no prompt, and the status is the one of the last command
+        let output = crsh!(; stdin = "echo one\nif true\nthen echo two\nfi\n(exit 3)\n");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\n");
+        assert_eq!(Some(3), output.status.code());
+    }
+
+    #[test]
+    fn piped_script_shares_stdin() {
+        // commands reading stdin get the lines after their own
+        let output = crsh!(; stdin = "cat\nhello\n");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn command_string() {
+        let output = crsh!("-c", "echo $0 $1; exit 4", "name", "arg");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "name arg\n");
+        assert_eq!(Some(4), output.status.code());
+
+        let output = crsh!("-c");
+        assert_eq!(Some(2), output.status.code());
+    }
+}
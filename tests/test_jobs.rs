@@ -0,0 +1,46 @@
+mod utils;
+
+#[cfg(test)]
+mod tests {
+    use crate::crsh;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn background_job_process_group() {
+        // the first process of a background job leads a process group of its own
+        let output = crsh!("-c", "sleep 0.2 & echo $!; ps -o pgid= -p $!");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().map(str::trim).collect();
+        assert_eq!(2, lines.len(), "unexpected output {:?}", stdout);
+        assert_eq!(lines[0], lines[1]);
+    }
+
+    #[test]
+    fn stopped_job() {
+        // a stopped foreground job gives back control and stays in the job table
+        let output = crsh!("-c", "sh -c 'kill -STOP $$'; echo $?; jobs; kill -KILL %1");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(3, lines.len(), "unexpected output {:?}", stdout);
+        assert!(lines[0].contains("Stopped"));
+        assert_eq!("148", lines[1]);
+        assert!(lines[2].starts_with("[1]") && lines[2].contains("Stopped"));
+    }
+
+    #[test]
+    fn wait_blocks_until_done() {
+        let start = Instant::now();
+        let output = crsh!("-c", "sleep 0.2 & wait; echo done");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "done\n");
+    }
+
+    #[test]
+    fn wait_status() {
+        let output = crsh!(
+            "-c",
+            "(exit 3) & sleep 0.1; wait $!; echo $?; false | (exit 4); echo $?"
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n4\n");
+    }
+}
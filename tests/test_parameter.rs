@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod test_parameter {
+    use crsh::ast::ReplaceMode;
+    use crsh::parameter::{change_case, remove_prefix, remove_suffix, replace, substring};
+    use crsh::pattern::Pattern;
+
+    #[test]
+    fn remove_affixes() {
+        let path = "/usr/lib/file.tar.gz";
+        assert_eq!(
+            "usr/lib/file.tar.gz",
+            remove_prefix(path, &Pattern::new("*/"), false)
+        );
+        assert_eq!(
+            "file.tar.gz",
+            remove_prefix(path, &Pattern::new("*/"), true)
+        );
+        assert_eq!(
+            "/usr/lib/file.tar",
+            remove_suffix(path, &Pattern::new(".*"), false)
+        );
+        assert_eq!(
+            "/usr/lib/file",
+            remove_suffix(path, &Pattern::new(".*"), true)
+        );
+        assert_eq!(path, remove_suffix(path, &Pattern::new("*.zip"), true));
+    }
+
+    #[test]
+    fn replace_matches() {
+        // the longest match at the first position that matches
+        let pattern = Pattern::new("a*b");
+        assert_eq!("x-", replace("xabcab", &pattern, "-", ReplaceMode::First));
+        let pattern = Pattern::new("b");
+        assert_eq!(
+            "xa-cab",
+            replace("xabcab", &pattern, "-", ReplaceMode::First)
+        );
+        assert_eq!(
+            "ab",
+            replace("ab", &Pattern::new("x"), "-", ReplaceMode::All)
+        );
+        let pattern = Pattern::new("[ab]");
+        assert_eq!("x--c--", replace("xabcab", &pattern, "-", ReplaceMode::All));
+        assert_eq!(
+            "xabcab",
+            replace("xabcab", &pattern, "-", ReplaceMode::Prefix)
+        );
+        assert_eq!(
+            "xabca-",
+            replace("xabcab", &pattern, "-", ReplaceMode::Suffix)
+        );
+        // an empty pattern only matches at the ends
+        let empty = Pattern::new("");
+        assert_eq!("abc", replace("abc", &empty, "-", ReplaceMode::All));
+        assert_eq!("-abc", replace("abc", &empty, "-", ReplaceMode::Prefix));
+    }
+
+    #[test]
+    fn substrings() {
+        assert_eq!(Some("lue".into()), substring("value", 2, None));
+        assert_eq!(Some("al".into()), substring("value", 1, Some(2)));
+        assert_eq!(Some("ue".into()), substring("value", -2, None));
+        assert_eq!(Some("alu".into()), substring("value", 1, Some(-1)));
+        assert_eq!(Some("".into()), substring("value", 9, None));
+        assert_eq!(Some("".into()), substring("value", -9, None));
+        assert_eq!(None, substring("value", 3, Some(-3)));
+        assert_eq!(Some("ñö".into()), substring("äñö", 1, None));
+    }
+
+    #[test]
+    fn change_cases() {
+        assert_eq!("Hello world", change_case("hello world", true, false));
+        assert_eq!("HELLO", change_case("hello", true, true));
+        assert_eq!("hELLO", change_case("HELLO", false, false));
+        assert_eq!("äbc", change_case("ÄBC", false, true));
+        assert_eq!("", change_case("", true, false));
+    }
+}
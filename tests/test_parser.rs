@@ -2,9 +2,10 @@ mod utils;
 
 #[cfg(test)]
 mod tests {
-    use crate::{reg_token, string_vec};
-    use crsh::ast::Node;
-    use crsh::parser::Parser;
+    use crate::{reg_token, word_vec};
+    use crsh::ast::WordPart;
+    use crsh::ast::{CaseItem, CaseTerminator, Node};
+    use crsh::parser::{ParseError, Parser};
     use crsh::token::Token;
 
     #[test]
@@ -16,7 +17,7 @@ mod tests {
             reg_token!("-b"),
             Token::EOF,
         ];
-        let expected = Node::Pipeline(vec![Node::Command(string_vec!("ls", "-a", "-b"), vec![])]);
+        let expected = Node::Pipeline(vec![Node::Command(word_vec!("ls", "-a", "-b"), vec![])]);
         let parser = Parser::new(tokens);
         assert_eq!(expected, parser.parse().unwrap());
     }
@@ -34,9 +35,9 @@ mod tests {
             reg_token!("wc"),
             Token::EOF,
         ];
-        let cmd0 = Node::Command(string_vec!("cat", "myfile"), vec![]);
-        let cmd1 = Node::Command(string_vec!("grep", "-r"), vec![]);
-        let cmd2 = Node::Command(string_vec!("wc"), vec![]);
+        let cmd0 = Node::Command(word_vec!("cat", "myfile"), vec![]);
+        let cmd1 = Node::Command(word_vec!("grep", "-r"), vec![]);
+        let cmd2 = Node::Command(word_vec!("wc"), vec![]);
         let expected = Node::Pipeline(vec![cmd0, cmd1, cmd2]);
         let parser = Parser::new(tokens);
         assert_eq!(expected, parser.parse().unwrap());
@@ -54,10 +55,10 @@ mod tests {
             Token::EOF,
         ];
         let redirect_vec = vec![
-            Node::RedirectRead("input".into()),
-            Node::RedirectWrite("output".into()),
+            Node::RedirectRead(0, Box::new(Node::Word("input".into()))),
+            Node::RedirectWrite(1, Box::new(Node::Word("output".into()))),
         ];
-        let expected = Node::Pipeline(vec![Node::Command(string_vec!("grep", "hi"), redirect_vec)]);
+        let expected = Node::Pipeline(vec![Node::Command(word_vec!("grep", "hi"), redirect_vec)]);
         let parser = Parser::new(tokens);
         assert_eq!(expected, parser.parse().unwrap());
     }
@@ -68,17 +69,695 @@ mod tests {
             reg_token!("grep"),
             reg_token!("hi"),
             reg_token!("myfile"),
-            Token::RRedirect,
-            Token::RRedirect,
+            Token::RAppend,
             reg_token!("output"),
             Token::EOF,
         ];
-        let redirect_vec = vec![Node::RedirectAppend("output".into())];
+        let redirect_vec = vec![Node::RedirectAppend(
+            1,
+            Box::new(Node::Word("output".into())),
+        )];
         let expected = Node::Pipeline(vec![Node::Command(
-            string_vec!("grep", "hi", "myfile"),
+            word_vec!("grep", "hi", "myfile"),
             redirect_vec,
         )]);
         let parser = Parser::new(tokens);
         assert_eq!(expected, parser.parse().unwrap());
     }
+
+    #[test]
+    fn parse_expansion() {
+        // "echo $HOME/bin "${USER}""
+        let tokens = vec![
+            reg_token!("echo"),
+            Token::Expansion(vec![
+                WordPart::Variable("HOME".into()),
+                WordPart::Literal("/bin".into()),
+            ]),
+            Token::Expansion(vec![WordPart::DoubleQuoted(vec![WordPart::Variable(
+                "USER".into(),
+            )])]),
+            Token::EOF,
+        ];
+        let words = vec![
+            Node::Word("echo".into()),
+            Node::ExpandableWord(vec![
+                WordPart::Variable("HOME".into()),
+                WordPart::Literal("/bin".into()),
+            ]),
+            Node::ExpandableWord(vec![WordPart::DoubleQuoted(vec![WordPart::Variable(
+                "USER".into(),
+            )])]),
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_assignment() {
+        // "FOO=1 make CC=$CC"
+        let tokens = vec![
+            Token::Assignment("FOO".into()),
+            reg_token!("1"),
+            reg_token!("make"),
+            Token::Assignment("CC".into()),
+            Token::Expansion(vec![WordPart::Variable("CC".into())]),
+            Token::EOF,
+        ];
+        let words = vec![
+            Node::Assignment("FOO".into(), Box::new(Node::Word("1".into()))),
+            Node::Word("make".into()),
+            Node::ExpandableWord(vec![
+                WordPart::Literal("CC=".into()),
+                WordPart::Variable("CC".into()),
+            ]),
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_and_or() {
+        // "make && ./run || cat log | wc; ls"
+        let tokens = vec![
+            reg_token!("make"),
+            Token::And,
+            reg_token!("./run"),
+            Token::Or,
+            reg_token!("cat"),
+            reg_token!("log"),
+            Token::Pipe,
+            reg_token!("wc"),
+            Token::CommandSeparator,
+            reg_token!("ls"),
+            Token::EOF,
+        ];
+        let make = Node::Pipeline(vec![Node::Command(word_vec!("make"), vec![])]);
+        let run = Node::Pipeline(vec![Node::Command(word_vec!("./run"), vec![])]);
+        let cat = Node::Pipeline(vec![
+            Node::Command(word_vec!("cat", "log"), vec![]),
+            Node::Command(word_vec!("wc"), vec![]),
+        ]);
+        let ls = Node::Pipeline(vec![Node::Command(word_vec!("ls"), vec![])]);
+        let and = Node::And(Box::new(make), Box::new(run));
+        let expected = Node::CommandSequence(vec![Node::Or(Box::new(and), Box::new(cat)), ls]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_background() {
+        // "sleep 10 & ls;"
+        let tokens = vec![
+            reg_token!("sleep"),
+            reg_token!("10"),
+            Token::Background,
+            reg_token!("ls"),
+            Token::CommandSeparator,
+            Token::EOF,
+        ];
+        let sleep = Node::Pipeline(vec![Node::Command(word_vec!("sleep", "10"), vec![])]);
+        let ls = Node::Pipeline(vec![Node::Command(word_vec!("ls"), vec![])]);
+        let expected = Node::CommandSequence(vec![Node::Background(Box::new(sleep)), ls]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_trailing_background() {
+        // "sleep 10 &"
+        let tokens = vec![
+            reg_token!("sleep"),
+            reg_token!("10"),
+            Token::Background,
+            Token::EOF,
+        ];
+        let sleep = Node::Pipeline(vec![Node::Command(word_vec!("sleep", "10"), vec![])]);
+        let expected = Node::Background(Box::new(sleep));
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_fd_redirect() {
+        // "make 2> log 2>&1 3<&- 1<> file"
+        let tokens = vec![
+            reg_token!("make"),
+            Token::IoNumber(2),
+            Token::RRedirect,
+            reg_token!("log"),
+            Token::IoNumber(2),
+            Token::RDuplicate,
+            reg_token!("1"),
+            Token::IoNumber(3),
+            Token::LDuplicate,
+            reg_token!("-"),
+            Token::IoNumber(1),
+            Token::ReadWrite,
+            reg_token!("file"),
+            Token::EOF,
+        ];
+        let redirect_vec = vec![
+            Node::RedirectWrite(2, Box::new(Node::Word("log".into()))),
+            Node::RedirectDuplicate(2, Box::new(Node::Word("1".into()))),
+            Node::RedirectDuplicate(3, Box::new(Node::Word("-".into()))),
+            Node::RedirectReadWrite(1, Box::new(Node::Word("file".into()))),
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(word_vec!("make"), redirect_vec)]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_redirect_all() {
+        // "make &>> log"
+        let tokens = vec![
+            reg_token!("make"),
+            Token::AppendAll,
+            reg_token!("log"),
+            Token::EOF,
+        ];
+        let redirect_vec = vec![
+            Node::RedirectAppend(1, Box::new(Node::Word("log".into()))),
+            Node::RedirectDuplicate(2, Box::new(Node::Word("1".into()))),
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(word_vec!("make"), redirect_vec)]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_heredoc() {
+        // "cat <<EOF <<< $X" with the body "hi\n"
+        let tokens = vec![
+            reg_token!("cat"),
+            Token::HereDoc("EOF".into(), vec![WordPart::Literal("hi\n".into())]),
+            Token::HereString,
+            Token::Expansion(vec![WordPart::Variable("X".into())]),
+            Token::EOF,
+        ];
+        let redirect_vec = vec![
+            Node::RedirectHereDoc(0, "EOF".into(), Box::new(Node::Word("hi\n".into()))),
+            Node::RedirectHereString(
+                0,
+                Box::new(Node::ExpandableWord(vec![WordPart::Variable("X".into())])),
+            ),
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(word_vec!("cat"), redirect_vec)]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_brace() {
+        // "cp file{,.bak} {a,b}$X{1,2}"
+        let tokens = vec![
+            reg_token!("cp"),
+            Token::Expansion(vec![
+                WordPart::Literal("file".into()),
+                WordPart::Brace(vec![vec![], vec![WordPart::Literal(".bak".into())]]),
+            ]),
+            Token::Expansion(vec![
+                WordPart::Brace(vec![
+                    vec![WordPart::Literal("a".into())],
+                    vec![WordPart::Literal("b".into())],
+                ]),
+                WordPart::Variable("X".into()),
+                WordPart::Brace(vec![
+                    vec![WordPart::Literal("1".into())],
+                    vec![WordPart::Literal("2".into())],
+                ]),
+            ]),
+            Token::EOF,
+        ];
+        let expanded = |prefix: &str, suffix: &str| {
+            Node::ExpandableWord(vec![
+                WordPart::Literal(prefix.into()),
+                WordPart::Variable("X".into()),
+                WordPart::Literal(suffix.into()),
+            ])
+        };
+        let mut command = word_vec!("cp", "file", "file.bak");
+        command.extend([
+            expanded("a", "1"),
+            expanded("a", "2"),
+            expanded("b", "1"),
+            expanded("b", "2"),
+        ]);
+        let expected = Node::Pipeline(vec![Node::Command(command, vec![])]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_subshell_group() {
+        // "(cd src; make) | { cat; } > log"
+        let tokens = vec![
+            Token::SubshellStart,
+            reg_token!("cd"),
+            reg_token!("src"),
+            Token::CommandSeparator,
+            reg_token!("make"),
+            Token::SubshellEnd,
+            Token::Pipe,
+            reg_token!("{"),
+            reg_token!("cat"),
+            Token::CommandSeparator,
+            reg_token!("}"),
+            Token::RRedirect,
+            reg_token!("log"),
+            Token::EOF,
+        ];
+        let subshell = Node::Subshell(
+            Box::new(Node::CommandSequence(vec![
+                Node::Pipeline(vec![Node::Command(word_vec!("cd", "src"), vec![])]),
+                Node::Pipeline(vec![Node::Command(word_vec!("make"), vec![])]),
+            ])),
+            vec![],
+        );
+        let group = Node::Group(
+            Box::new(Node::Pipeline(vec![Node::Command(
+                word_vec!("cat"),
+                vec![],
+            )])),
+            vec![Node::RedirectWrite(1, Box::new(Node::Word("log".into())))],
+        );
+        let expected = Node::Pipeline(vec![subshell, group]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_unclosed_group() {
+        // "{ echo }"
+        let tokens = vec![
+            reg_token!("{"),
+            reg_token!("echo"),
+            reg_token!("}"),
+            Token::EOF,
+        ];
+        let parser = Parser::new(tokens);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn parse_if() {
+        // "if a; then b\nelif c; then d\nelse echo fi; fi"
+        let tokens = vec![
+            reg_token!("if"),
+            reg_token!("a"),
+            Token::CommandSeparator,
+            reg_token!("then"),
+            reg_token!("b"),
+            Token::Newline,
+            reg_token!("elif"),
+            reg_token!("c"),
+            Token::CommandSeparator,
+            reg_token!("then"),
+            reg_token!("d"),
+            Token::Newline,
+            reg_token!("else"),
+            reg_token!("echo"),
+            reg_token!("fi"),
+            Token::CommandSeparator,
+            reg_token!("fi"),
+            Token::EOF,
+        ];
+        let command = |words| Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let expected = Node::Pipeline(vec![Node::If(
+            vec![
+                (command(word_vec!("a")), command(word_vec!("b"))),
+                (command(word_vec!("c")), command(word_vec!("d"))),
+            ],
+            Some(Box::new(command(word_vec!("echo", "fi")))),
+            vec![],
+        )]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_while() {
+        // "while a &&\nb\ndo c; done < in"
+        let tokens = vec![
+            reg_token!("while"),
+            reg_token!("a"),
+            Token::And,
+            Token::Newline,
+            reg_token!("b"),
+            Token::Newline,
+            reg_token!("do"),
+            reg_token!("c"),
+            Token::CommandSeparator,
+            reg_token!("done"),
+            Token::LRedirect,
+            reg_token!("in"),
+            Token::EOF,
+        ];
+        let command = |words| Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let expected = Node::Pipeline(vec![Node::While(
+            Box::new(Node::And(
+                Box::new(command(word_vec!("a"))),
+                Box::new(command(word_vec!("b"))),
+            )),
+            Box::new(command(word_vec!("c"))),
+            vec![Node::RedirectRead(0, Box::new(Node::Word("in".into())))],
+        )]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_incomplete() {
+        // "until a; do" and "a |"
+        let inputs = [
+            vec![
+                reg_token!("until"),
+                reg_token!("a"),
+                Token::CommandSeparator,
+                reg_token!("do"),
+                Token::Newline,
+                Token::EOF,
+            ],
+            vec![reg_token!("a"), Token::Pipe, Token::EOF],
+        ];
+        for tokens in inputs {
+            let parser = Parser::new(tokens);
+            assert!(matches!(parser.parse(), Err(ParseError::Incomplete(_))));
+        }
+    }
+
+    #[test]
+    fn parse_for() {
+        // "for i in a b\ndo c $i; done" and "select i; do c; done"
+        let tokens = vec![
+            reg_token!("for"),
+            reg_token!("i"),
+            reg_token!("in"),
+            reg_token!("a"),
+            reg_token!("b"),
+            Token::Newline,
+            reg_token!("do"),
+            reg_token!("c"),
+            Token::Expansion(vec![WordPart::Variable("i".into())]),
+            Token::CommandSeparator,
+            reg_token!("done"),
+            Token::CommandSeparator,
+            reg_token!("select"),
+            reg_token!("i"),
+            Token::CommandSeparator,
+            reg_token!("do"),
+            reg_token!("c"),
+            Token::CommandSeparator,
+            reg_token!("done"),
+            Token::EOF,
+        ];
+        let command = |words| Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let mut words = word_vec!("c");
+        words.push(Node::ExpandableWord(vec![WordPart::Variable("i".into())]));
+        let expected = Node::CommandSequence(vec![
+            Node::Pipeline(vec![Node::For(
+                "i".into(),
+                Some(word_vec!("a", "b")),
+                Box::new(command(words)),
+                vec![],
+            )]),
+            Node::Pipeline(vec![Node::Select(
+                "i".into(),
+                None,
+                Box::new(command(word_vec!("c"))),
+                vec![],
+            )]),
+        ]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_arith_for() {
+        // "for ((i = 0; i < 3; i++)) do c; done"
+        let tokens = vec![
+            reg_token!("for"),
+            Token::Arithmetic("i = 0; i < 3; i++".into()),
+            reg_token!("do"),
+            reg_token!("c"),
+            Token::CommandSeparator,
+            reg_token!("done"),
+            Token::EOF,
+        ];
+        let Node::Pipeline(commands) = Parser::new(tokens).parse().unwrap() else {
+            panic!("Expected a pipeline");
+        };
+        let Node::ArithFor(init, condition, step, _, _) = &commands[0] else {
+            panic!("Expected an arithmetic for loop");
+        };
+        assert_eq!(
+            ("i = 0", "i < 3", "i++"),
+            (init.trim(), condition.trim(), step.trim())
+        );
+        // the expression needs exactly three parts
+        let tokens = vec![
+            reg_token!("for"),
+            Token::Arithmetic("i = 0; i < 3".into()),
+            reg_token!("do"),
+            reg_token!("c"),
+            Token::CommandSeparator,
+            reg_token!("done"),
+            Token::EOF,
+        ];
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn parse_case() {
+        // "case x in a|b) c;; (d) e;&\n*) ;;& esac"
+        let tokens = vec![
+            reg_token!("case"),
+            reg_token!("x"),
+            reg_token!("in"),
+            reg_token!("a"),
+            Token::Pipe,
+            reg_token!("b"),
+            Token::SubshellEnd,
+            reg_token!("c"),
+            Token::CaseBreak,
+            Token::SubshellStart,
+            reg_token!("d"),
+            Token::SubshellEnd,
+            reg_token!("e"),
+            Token::CaseFallThrough,
+            Token::Newline,
+            Token::Expansion(vec![WordPart::Glob("*".into())]),
+            Token::SubshellEnd,
+            Token::CaseContinue,
+            reg_token!("esac"),
+            Token::EOF,
+        ];
+        let command = |words| Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let expected = Node::Pipeline(vec![Node::Case(
+            Box::new(Node::Word("x".into())),
+            vec![
+                CaseItem {
+                    patterns: word_vec!("a", "b"),
+                    body: command(word_vec!("c")),
+                    terminator: CaseTerminator::Break,
+                },
+                CaseItem {
+                    patterns: word_vec!("d"),
+                    body: command(word_vec!("e")),
+                    terminator: CaseTerminator::FallThrough,
+                },
+                CaseItem {
+                    patterns: vec![Node::ExpandableWord(vec![WordPart::Glob("*".into())])],
+                    body: Node::CommandSequence(vec![]),
+                    terminator: CaseTerminator::Continue,
+                },
+            ],
+            vec![],
+        )]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_function() {
+        // "f() { a; } > out; function g\n(b)" and "function h() c"
+        let tokens = vec![
+            reg_token!("f"),
+            Token::SubshellStart,
+            Token::SubshellEnd,
+            reg_token!("{"),
+            reg_token!("a"),
+            Token::CommandSeparator,
+            reg_token!("}"),
+            Token::RRedirect,
+            reg_token!("out"),
+            Token::CommandSeparator,
+            reg_token!("function"),
+            reg_token!("g"),
+            Token::Newline,
+            Token::SubshellStart,
+            reg_token!("b"),
+            Token::SubshellEnd,
+            Token::EOF,
+        ];
+        let command = |words| Node::Pipeline(vec![Node::Command(words, vec![])]);
+        let expected = Node::CommandSequence(vec![
+            Node::Pipeline(vec![Node::FunctionDef(
+                "f".into(),
+                Box::new(Node::Group(
+                    Box::new(command(word_vec!("a"))),
+                    vec![Node::RedirectWrite(1, Box::new(Node::Word("out".into())))],
+                )),
+            )]),
+            Node::Pipeline(vec![Node::FunctionDef(
+                "g".into(),
+                Box::new(Node::Subshell(Box::new(command(word_vec!("b"))), vec![])),
+            )]),
+        ]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+        // the body has to be a compound command
+        let tokens = vec![
+            reg_token!("function"),
+            reg_token!("h"),
+            Token::SubshellStart,
+            Token::SubshellEnd,
+            reg_token!("c"),
+            Token::EOF,
+        ];
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn parse_arithmetic_command() {
+        // "((x++)) > out && ((y))"
+        let tokens = vec![
+            Token::Arithmetic("x++".into()),
+            Token::RRedirect,
+            reg_token!("out"),
+            Token::And,
+            Token::Arithmetic("y".into()),
+            Token::EOF,
+        ];
+        let expected = Node::And(
+            Box::new(Node::Pipeline(vec![Node::Arithmetic(
+                "x++".into(),
+                vec![Node::RedirectWrite(1, Box::new(Node::Word("out".into())))],
+            )])),
+            Box::new(Node::Pipeline(vec![Node::Arithmetic("y".into(), vec![])])),
+        );
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn parse_arrays() {
+        // "arr=(a{1,2} [5]=b) s+=x local l=(c) echo m+=(d)"
+        let tokens = vec![
+            Token::Assignment("arr".into()),
+            Token::SubshellStart,
+            Token::Expansion(vec![
+                WordPart::Literal("a".into()),
+                WordPart::Brace(vec![
+                    vec![WordPart::Literal("1".into())],
+                    vec![WordPart::Literal("2".into())],
+                ]),
+            ]),
+            Token::Subscript("5".into()),
+            reg_token!("b"),
+            Token::SubshellEnd,
+            Token::AppendAssignment("s".into()),
+            reg_token!("x"),
+            reg_token!("local"),
+            Token::Assignment("l".into()),
+            Token::SubshellStart,
+            reg_token!("c"),
+            Token::SubshellEnd,
+            Token::EOF,
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(
+            vec![
+                Node::Assignment(
+                    "arr".into(),
+                    Box::new(Node::Array(vec![
+                        (None, Node::Word("a1".into())),
+                        (None, Node::Word("a2".into())),
+                        (Some("5".into()), Node::Word("b".into())),
+                    ])),
+                ),
+                Node::AppendAssignment("s".into(), Box::new(Node::Word("x".into()))),
+                Node::Word("local".into()),
+                Node::Assignment(
+                    "l".into(),
+                    Box::new(Node::Array(vec![(None, Node::Word("c".into()))])),
+                ),
+            ],
+            vec![],
+        )]);
+        let parser = Parser::new(tokens);
+        assert_eq!(expected, parser.parse().unwrap());
+        // other commands can't take arrays
+        let tokens = vec![
+            reg_token!("echo"),
+            Token::AppendAssignment("m".into()),
+            Token::SubshellStart,
+            reg_token!("d"),
+            Token::SubshellEnd,
+            Token::EOF,
+        ];
+        assert!(Parser::new(tokens).parse().is_err());
+        let tokens = vec![
+            Token::Assignment("arr".into()),
+            Token::SubshellStart,
+            reg_token!("a"),
+            Token::EOF,
+        ];
+        assert!(matches!(
+            Parser::new(tokens).parse(),
+            Err(ParseError::Incomplete(_))
+        ));
+    }
+
+    #[test]
+    fn parse_declarations() {
+        // "export E=$x echo=*" and "echo y=$x"
+        let value = || vec![WordPart::Variable("x".into())];
+        let tokens = vec![
+            reg_token!("export"),
+            Token::Assignment("E".into()),
+            Token::Expansion(value()),
+            Token::Assignment("echo".into()),
+            Token::Expansion(vec![WordPart::Glob("*".into())]),
+            Token::EOF,
+        ];
+        let expected = Node::Pipeline(vec![Node::Command(
+            vec![
+                Node::Word("export".into()),
+                Node::Assignment("E".into(), Box::new(Node::ExpandableWord(value()))),
+                Node::Assignment(
+                    "echo".into(),
+                    Box::new(Node::ExpandableWord(vec![WordPart::Glob("*".into())])),
+                ),
+            ],
+            vec![],
+        )]);
+        assert_eq!(expected, Parser::new(tokens).parse().unwrap());
+        // for other commands they are words
+        let tokens = vec![
+            reg_token!("echo"),
+            Token::Assignment("y".into()),
+            Token::Expansion(value()),
+            Token::EOF,
+        ];
+        let mut word = vec![WordPart::Literal("y=".into())];
+        word.extend(value());
+        let expected = Node::Pipeline(vec![Node::Command(
+            vec![Node::Word("echo".into()), Node::ExpandableWord(word)],
+            vec![],
+        )]);
+        assert_eq!(expected, Parser::new(tokens).parse().unwrap());
+    }
 }
@@ -0,0 +1,36 @@
+#[cfg(test)]
+mod test_pattern {
+    use crsh::pattern::{escape, Pattern};
+
+    #[test]
+    fn match_wildcards() {
+        let pattern = Pattern::new("*.r?");
+        assert!(pattern.matches("main.rs"));
+        assert!(pattern.matches(".rs"));
+        assert!(!pattern.matches("main.rs.bak"));
+        assert!(!pattern.matches("main.r"));
+        assert!(Pattern::new("a*b*c").matches("aXbYbZc"));
+        assert!(!Pattern::new("a*b*c").matches("aXbYbZ"));
+    }
+
+    #[test]
+    fn match_brackets() {
+        let pattern = Pattern::new("[a-c][!0-9][[:digit:]]");
+        assert!(pattern.matches("bx1"));
+        assert!(!pattern.matches("dx1"));
+        assert!(!pattern.matches("b01"));
+        assert!(Pattern::new("[]!]").matches("]"));
+        assert!(Pattern::new("[a-]").matches("-"));
+        // without a closing bracket "[" is an ordinary character
+        assert!(Pattern::new("[a").matches("[a"));
+    }
+
+    #[test]
+    fn match_escaped() {
+        let pattern = Pattern::new(&escape("*[x].rs"));
+        assert!(pattern.is_literal());
+        assert_eq!("*[x].rs", pattern.literal());
+        assert!(pattern.matches("*[x].rs"));
+        assert!(!pattern.matches("a[x].rs"));
+    }
+}
@@ -0,0 +1,32 @@
+mod utils;
+
+#[cfg(test)]
+mod tests {
+    use crate::crsh;
+
+    #[test]
+    fn pipeline_closes_unused_ends() {
+        // yes only stops once head exits and the pipe has no other reader
+        let output = crsh!("-c", "yes | head -1");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "y\n");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn background_notice_needs_job_control() {
+        // "[1] pid" is only printed by interactive shells
+        let output = crsh!("-c", "true & wait; echo done");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "done\n");
+    }
+
+    #[test]
+    fn builtin_into_closed_pipe() {
+        // a copy of the shell writing to a pipe nobody reads dies of SIGPIPE instead of aborting
+        let output = crsh!(
+            "-c",
+            "BIG=$(seq 1 20000); export BIG; (export) | true; export | true; echo ok"
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+        assert!(output.stderr.is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+mod utils;
+
+#[cfg(test)]
+mod tests {
+    use crate::crsh;
+    use std::fs::{create_dir_all, read_to_string};
+    use std::path::PathBuf;
+    use std::process::Output;
+
+    // runs a command string through the built shell with -c, in a directory of its own
+    fn crsh_in(dir: &str, command: &str) -> (Output, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("crsh-{}-{}", std::process::id(), dir));
+        create_dir_all(&dir).unwrap();
+        (crsh!("-c", command; current_dir = &dir), dir)
+    }
+
+    #[test]
+    fn redirect_order() {
+        // stderr goes where stdout pointed when it was duplicated, here the output of crsh
+        let (output, dir) = crsh_in(
+            "order",
+            "ls /nonexistent 2>&1 >first; ls /nonexistent >second 2>&1",
+        );
+        assert!(!output.stdout.is_empty());
+        assert_eq!("", read_to_string(dir.join("first")).unwrap());
+        assert!(!read_to_string(dir.join("second")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn builtin_redirects() {
+        let (output, dir) = crsh_in("builtin", "shopt >options; cd /nonexistent 2>errors");
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+        assert!(read_to_string(dir.join("options"))
+            .unwrap()
+            .contains("failglob"));
+        assert!(!read_to_string(dir.join("errors")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn builtin_pipeline() {
+        // builtins in a pipeline run in a subshell, so cd doesn't change the directory
+        let (output, dir) = crsh_in("pipeline", "export | grep -c ' HOME='; cd / | cat; pwd -P");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(2, lines.len(), "unexpected output {:?}", stdout);
+        assert_eq!("1", lines[0]);
+        assert_eq!(dir.canonicalize().unwrap().to_str(), Some(lines[1]));
+    }
+
+    #[test]
+    fn failed_redirect() {
+        // only the command with the redirect fails, the rest of the list goes on
+        let (output, _) = crsh_in(
+            "failed",
+            "cat < missing || echo fallback; for i in 1; do cat < missing; done; echo $?; echo >&5",
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "fallback\n1\n");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "crsh: missing: No such file or directory\n".repeat(2)
+                + "crsh: 5: Bad file descriptor\n"
+        );
+        assert_eq!(Some(1), output.status.code());
+    }
+}
@@ -2,8 +2,9 @@ mod utils;
 
 #[cfg(test)]
 mod test_scanner {
-    use crate::reg_token;
-    use crsh::scanner::Scanner;
+    use crate::{reg_token, word_vec};
+    use crsh::ast::{Node, ParameterOperator, ReplaceMode, WordPart};
+    use crsh::scanner::{ScanError, Scanner};
     use crsh::token::Token;
 
     #[test]
@@ -13,6 +14,7 @@ mod test_scanner {
             reg_token!("ls"),
             reg_token!("-a"),
             reg_token!("-b"),
+            Token::Newline,
             Token::EOF,
         ];
         let scanner = Scanner::new(command.into());
@@ -31,6 +33,7 @@ mod test_scanner {
             reg_token!("-r"),
             Token::Pipe,
             reg_token!("wc"),
+            Token::Newline,
             Token::EOF,
         ];
         let scanner = Scanner::new(command.into());
@@ -70,4 +73,707 @@ mod test_scanner {
         let tokens = scanner.scan_tokens().unwrap();
         assert_eq!(expected[..], tokens[..]);
     }
+
+    #[test]
+    fn scan_expansion() {
+        let command = "echo $HOME/bin ${USER}x $ $1";
+        let expected = [
+            reg_token!("echo"),
+            Token::Expansion(vec![
+                WordPart::Variable("HOME".into()),
+                WordPart::Literal("/bin".into()),
+            ]),
+            Token::Expansion(vec![
+                WordPart::Variable("USER".into()),
+                WordPart::Literal("x".into()),
+            ]),
+            reg_token!("$"),
+            Token::Expansion(vec![WordPart::Variable("1".into())]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_quoted_expansion() {
+        let command = "echo \"dir: $PWD\" '$PWD'";
+        let expected = [
+            reg_token!("echo"),
+            Token::Expansion(vec![WordPart::DoubleQuoted(vec![
+                WordPart::Literal("dir: ".into()),
+                WordPart::Variable("PWD".into()),
+            ])]),
+            reg_token!("$PWD"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_bad_substitution() {
+        let scanner = Scanner::new("echo ${HOME".into());
+        assert!(scanner.scan_tokens().is_err());
+    }
+
+    #[test]
+    fn scan_assignment() {
+        let command = "FOO=1 BAR= make CC=$CC --opt=x";
+        let expected = [
+            Token::Assignment("FOO".into()),
+            reg_token!("1"),
+            Token::Assignment("BAR".into()),
+            reg_token!(""),
+            reg_token!("make"),
+            Token::Assignment("CC".into()),
+            Token::Expansion(vec![WordPart::Variable("CC".into())]),
+            reg_token!("--opt=x"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_and_or() {
+        let command = "make && ./run || cat log|wc";
+        let expected = [
+            reg_token!("make"),
+            Token::And,
+            reg_token!("./run"),
+            Token::Or,
+            reg_token!("cat"),
+            reg_token!("log"),
+            Token::Pipe,
+            reg_token!("wc"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_special_parameter() {
+        let command = "echo $? ${?}";
+        let expected = [
+            reg_token!("echo"),
+            Token::Expansion(vec![WordPart::Variable("?".into())]),
+            Token::Expansion(vec![WordPart::Variable("?".into())]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_background() {
+        let command = "sleep 10& make && ./run &";
+        let expected = [
+            reg_token!("sleep"),
+            reg_token!("10"),
+            Token::Background,
+            reg_token!("make"),
+            Token::And,
+            reg_token!("./run"),
+            Token::Background,
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_positional_parameters() {
+        let command = "echo $0 $12 ${12} $# \"$@\"";
+        let expected = [
+            reg_token!("echo"),
+            Token::Expansion(vec![WordPart::Variable("0".into())]),
+            Token::Expansion(vec![
+                WordPart::Variable("1".into()),
+                WordPart::Literal("2".into()),
+            ]),
+            Token::Expansion(vec![WordPart::Variable("12".into())]),
+            Token::Expansion(vec![WordPart::Variable("#".into())]),
+            Token::Expansion(vec![WordPart::DoubleQuoted(vec![WordPart::Variable(
+                "@".into(),
+            )])]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_fd_redirect() {
+        let command = "cmd 2>err 2>>log >&2 3<&- 0<>file &>all &>>all x2>y";
+        let expected = [
+            reg_token!("cmd"),
+            Token::IoNumber(2),
+            Token::RRedirect,
+            reg_token!("err"),
+            Token::IoNumber(2),
+            Token::RAppend,
+            reg_token!("log"),
+            Token::RDuplicate,
+            reg_token!("2"),
+            Token::IoNumber(3),
+            Token::LDuplicate,
+            reg_token!("-"),
+            Token::IoNumber(0),
+            Token::ReadWrite,
+            reg_token!("file"),
+            Token::RedirectAll,
+            reg_token!("all"),
+            Token::AppendAll,
+            reg_token!("all"),
+            reg_token!("x2"),
+            Token::RRedirect,
+            reg_token!("y"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_heredoc() {
+        let command = "cat <<EOF 3<<-'END' <<<$X\nhi $USER\nEOF\n\t$USER\n\tEND\n";
+        let expected = [
+            reg_token!("cat"),
+            Token::HereDoc(
+                "EOF".into(),
+                vec![
+                    WordPart::Literal("hi ".into()),
+                    WordPart::Variable("USER".into()),
+                    WordPart::Literal("\n".into()),
+                ],
+            ),
+            Token::IoNumber(3),
+            Token::HereDoc("END".into(), vec![WordPart::Literal("$USER\n".into())]),
+            Token::HereString,
+            Token::Expansion(vec![WordPart::Variable("X".into())]),
+            Token::Newline,
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_unterminated_heredoc() {
+        let scanner = Scanner::new("cat <<EOF\nhi\n".into());
+        let res = scanner.scan_tokens();
+        assert!(
+            matches!(res, Err(ScanError::UnterminatedHereDoc(delimiter)) if delimiter == "EOF")
+        );
+    }
+
+    #[test]
+    fn scan_command_substitution() {
+        let command = "cd $(git rev-parse --show-toplevel)/src \"`pwd`\"";
+        let git = Node::Pipeline(vec![Node::Command(
+            word_vec!("git", "rev-parse", "--show-toplevel"),
+            vec![],
+        )]);
+        let pwd = Node::Pipeline(vec![Node::Command(word_vec!("pwd"), vec![])]);
+        let expected = [
+            reg_token!("cd"),
+            Token::Expansion(vec![
+                WordPart::CommandSubstitution(Box::new(git)),
+                WordPart::Literal("/src".into()),
+            ]),
+            Token::Expansion(vec![WordPart::DoubleQuoted(vec![
+                WordPart::CommandSubstitution(Box::new(pwd)),
+            ])]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_nested_command_substitution() {
+        let command = "echo $(basename $(pwd))";
+        let pwd = Node::Pipeline(vec![Node::Command(word_vec!("pwd"), vec![])]);
+        let basename = Node::Pipeline(vec![Node::Command(
+            vec![
+                Node::Word("basename".into()),
+                Node::ExpandableWord(vec![WordPart::CommandSubstitution(Box::new(pwd))]),
+            ],
+            vec![],
+        )]);
+        let expected = [
+            reg_token!("echo"),
+            Token::Expansion(vec![WordPart::CommandSubstitution(Box::new(basename))]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_glob() {
+        let command = "ls *.r? \"*.rs\" src/[a-z]*[[:digit:]] [ -f ]";
+        let expected = [
+            reg_token!("ls"),
+            Token::Expansion(vec![
+                WordPart::Glob("*".into()),
+                WordPart::Literal(".r".into()),
+                WordPart::Glob("?".into()),
+            ]),
+            reg_token!("*.rs"),
+            Token::Expansion(vec![
+                WordPart::Literal("src/".into()),
+                WordPart::Glob("[a-z]*[[:digit:]]".into()),
+            ]),
+            reg_token!("["),
+            reg_token!("-f"),
+            reg_token!("]"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_tilde() {
+        let command = "ls ~ ~user/src a~b ~x\"y\" PATH=~/bin:~+";
+        let expected = [
+            reg_token!("ls"),
+            Token::Expansion(vec![WordPart::Tilde("".into())]),
+            Token::Expansion(vec![
+                WordPart::Tilde("user".into()),
+                WordPart::Literal("/src".into()),
+            ]),
+            reg_token!("a~b"),
+            reg_token!("~xy"),
+            Token::Assignment("PATH".into()),
+            Token::Expansion(vec![
+                WordPart::Tilde("".into()),
+                WordPart::Literal("/bin:".into()),
+                WordPart::Tilde("+".into()),
+            ]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_brace() {
+        let command = "mkdir src/{bin,lib{,s}} {08..10..2} {c..a} {a} { X={a,b}";
+        let literals = |words: &[&str]| -> Vec<Vec<WordPart>> {
+            words
+                .iter()
+                .map(|word| match *word {
+                    "" => vec![],
+                    word => vec![WordPart::Literal(word.into())],
+                })
+                .collect()
+        };
+        let expected = [
+            reg_token!("mkdir"),
+            Token::Expansion(vec![
+                WordPart::Literal("src/".into()),
+                WordPart::Brace(vec![
+                    vec![WordPart::Literal("bin".into())],
+                    vec![
+                        WordPart::Literal("lib".into()),
+                        WordPart::Brace(literals(&["", "s"])),
+                    ],
+                ]),
+            ]),
+            Token::Expansion(vec![WordPart::Brace(literals(&["08", "10"]))]),
+            Token::Expansion(vec![WordPart::Brace(literals(&["c", "b", "a"]))]),
+            reg_token!("{a}"),
+            reg_token!("{"),
+            Token::Assignment("X".into()),
+            reg_token!("{a,b}"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_brace_overflow() {
+        // sequences that are too long stay as they are, and large steps don't overflow
+        let command = "{1..9223372036854775807} {-9223372036854775808..9223372036854775807..9223372036854775807}";
+        let expected = [
+            reg_token!("{1..9223372036854775807}"),
+            Token::Expansion(vec![WordPart::Brace(vec![
+                vec![WordPart::Literal("-9223372036854775808".into())],
+                vec![WordPart::Literal("-1".into())],
+                vec![WordPart::Literal("9223372036854775806".into())],
+            ])]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_subshell() {
+        let command = "(cd src) $( (pwd) )";
+        let substitution = Node::Subshell(
+            Box::new(Node::Pipeline(vec![Node::Command(
+                word_vec!("pwd"),
+                vec![],
+            )])),
+            vec![],
+        );
+        let expected = [
+            Token::SubshellStart,
+            reg_token!("cd"),
+            reg_token!("src"),
+            Token::SubshellEnd,
+            Token::Expansion(vec![WordPart::CommandSubstitution(Box::new(
+                Node::Pipeline(vec![substitution]),
+            ))]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_case() {
+        let command = "case $x in a|b) c;; (*.rs) e;& *) f;;& esac";
+        let expected = [
+            reg_token!("case"),
+            Token::Expansion(vec![WordPart::Variable("x".into())]),
+            reg_token!("in"),
+            reg_token!("a"),
+            Token::Pipe,
+            reg_token!("b"),
+            Token::SubshellEnd,
+            reg_token!("c"),
+            Token::CaseBreak,
+            Token::SubshellStart,
+            Token::Expansion(vec![
+                WordPart::Glob("*".into()),
+                WordPart::Literal(".rs".into()),
+            ]),
+            Token::SubshellEnd,
+            reg_token!("e"),
+            Token::CaseFallThrough,
+            Token::Expansion(vec![WordPart::Glob("*".into())]),
+            Token::SubshellEnd,
+            reg_token!("f"),
+            Token::CaseContinue,
+            reg_token!("esac"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_arithmetic_command() {
+        let command = "for ((i = 0; i < (3); i++)) ((x)) ( (y) )";
+        let expected = [
+            reg_token!("for"),
+            Token::Arithmetic("i = 0; i < (3); i++".into()),
+            Token::Arithmetic("x".into()),
+            Token::SubshellStart,
+            Token::SubshellStart,
+            reg_token!("y"),
+            Token::SubshellEnd,
+            Token::SubshellEnd,
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_arithmetic_expansion() {
+        let command = "echo $((x * (2 + 1))) \"$(($1))\" $((cd src); ls)";
+        let expected = [
+            reg_token!("echo"),
+            Token::Expansion(vec![WordPart::Arithmetic("x * (2 + 1)".into())]),
+            Token::Expansion(vec![WordPart::DoubleQuoted(vec![WordPart::Arithmetic(
+                "$1".into(),
+            )])]),
+            Token::Expansion(vec![WordPart::CommandSubstitution(Box::new(
+                Node::CommandSequence(vec![
+                    Node::Pipeline(vec![Node::Subshell(
+                        Box::new(Node::Pipeline(vec![Node::Command(
+                            word_vec!("cd", "src"),
+                            vec![],
+                        )])),
+                        vec![],
+                    )]),
+                    Node::Pipeline(vec![Node::Command(word_vec!("ls"), vec![])]),
+                ]),
+            ))]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+        let parts = Scanner::arithmetic_parts("$x + ${y} * $(z)").unwrap();
+        assert!(matches!(
+            parts.as_slice(),
+            [
+                WordPart::Variable(_),
+                WordPart::Literal(_),
+                WordPart::Variable(_),
+                WordPart::Literal(_),
+                WordPart::CommandSubstitution(_),
+            ]
+        ));
+    }
+
+    #[test]
+    fn scan_parameter_expansion() {
+        let command = "${#x} ${#} ${x:-$y z} ${x=a} \"${x##*/}\" ${x//'*'/-} ${x:1:n-1} ${x^^}";
+        let literal = |string: &str| WordPart::Literal(string.into());
+        let parameter =
+            |operator| Token::Expansion(vec![WordPart::Parameter("x".into(), operator)]);
+        let expected = [
+            parameter(ParameterOperator::Length),
+            Token::Expansion(vec![WordPart::Variable("#".into())]),
+            parameter(ParameterOperator::Default(
+                true,
+                vec![WordPart::Variable("y".into()), literal(" z")],
+            )),
+            parameter(ParameterOperator::Assign(false, vec![literal("a")])),
+            Token::Expansion(vec![WordPart::DoubleQuoted(vec![WordPart::Parameter(
+                "x".into(),
+                ParameterOperator::RemovePrefix(
+                    true,
+                    vec![WordPart::Glob("*".into()), literal("/")],
+                ),
+            )])]),
+            parameter(ParameterOperator::Replace(
+                ReplaceMode::All,
+                vec![literal("*")],
+                vec![literal("-")],
+            )),
+            parameter(ParameterOperator::Substring("1".into(), Some("n-1".into()))),
+            parameter(ParameterOperator::Case(true, true)),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+        for command in ["${x", "${x:-a", "${x!}", "${}"] {
+            let scanner = Scanner::new(command.into());
+            assert!(matches!(
+                scanner.scan_tokens(),
+                Err(ScanError::BadSubstitution(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn scan_arrays() {
+        let command = "arr=(a \"b c\"\n[5]=d) arr[i+1]=x s+=y m[k]+=z echo a[1]";
+        let expected = [
+            Token::Assignment("arr".into()),
+            Token::SubshellStart,
+            reg_token!("a"),
+            reg_token!("b c"),
+            Token::Subscript("5".into()),
+            reg_token!("d"),
+            Token::SubshellEnd,
+            Token::Assignment("arr[i+1]".into()),
+            reg_token!("x"),
+            Token::AppendAssignment("s".into()),
+            reg_token!("y"),
+            Token::AppendAssignment("m[k]".into()),
+            reg_token!("z"),
+            reg_token!("echo"),
+            Token::Expansion(vec![
+                WordPart::Literal("a".into()),
+                WordPart::Glob("[1]".into()),
+            ]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+        // the parser asks for the rest of an array that isn't closed
+        let scanner = Scanner::new("arr=(a\n".into());
+        let expected = [
+            Token::Assignment("arr".into()),
+            Token::SubshellStart,
+            reg_token!("a"),
+            Token::EOF,
+        ];
+        assert_eq!(expected[..], scanner.scan_tokens().unwrap()[..]);
+    }
+
+    #[test]
+    fn scan_array_expansion() {
+        let command = "${arr[1]} ${arr[$i]} ${#arr[@]} ${!map[@]} ${arr[*]#x}";
+        let parameter = |name: &str, operator| {
+            Token::Expansion(vec![WordPart::Parameter(name.into(), operator)])
+        };
+        let expected = [
+            Token::Expansion(vec![WordPart::Variable("arr[1]".into())]),
+            Token::Expansion(vec![WordPart::Variable("arr[$i]".into())]),
+            parameter("arr[@]", ParameterOperator::Length),
+            parameter("map[@]", ParameterOperator::Keys),
+            parameter(
+                "arr[*]",
+                ParameterOperator::RemovePrefix(false, vec![WordPart::Literal("x".into())]),
+            ),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+        for command in ["${arr[1}", "${arr[]}", "${!x}"] {
+            let scanner = Scanner::new(command.into());
+            assert!(matches!(
+                scanner.scan_tokens(),
+                Err(ScanError::BadSubstitution(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn scan_escapes() {
+        let command = r#"echo a\ b \$x "\$x \"\\ \a" \* 2\>"#;
+        let expected = [
+            reg_token!("echo"),
+            reg_token!("a b"),
+            reg_token!("$x"),
+            reg_token!("$x \"\\ \\a"),
+            reg_token!("*"),
+            reg_token!("2>"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_ansi_c_quoted() {
+        let command = r"echo $'a\tb\n' $'\x41\101é\cA' $'it\'s\q' $'\xz'";
+        let expected = [
+            reg_token!("echo"),
+            reg_token!("a\tb\n"),
+            reg_token!("AAé\x01"),
+            reg_token!("it's\\q"),
+            reg_token!("\\xz"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_concatenated_quotes() {
+        let command = "echo foo\"bar\"baz a\"$x\"'*'* '' \"if\" x\"\"=1";
+        let expected = [
+            reg_token!("echo"),
+            reg_token!("foobarbaz"),
+            Token::Expansion(vec![
+                WordPart::Literal("a".into()),
+                WordPart::DoubleQuoted(vec![WordPart::Variable("x".into())]),
+                WordPart::Literal("*".into()),
+                WordPart::Glob("*".into()),
+            ]),
+            reg_token!(""),
+            // quoted reserved words and assignments are ordinary words
+            Token::Expansion(vec![WordPart::Literal("if".into())]),
+            reg_token!("x=1"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_unterminated_quote() {
+        for (command, quote, position) in [
+            ("echo 'abc", '\'', 5),
+            ("echo a\"b", '"', 6),
+            ("echo $'x", '\'', 5),
+            ("echo ${x:-'a}", '\'', 10),
+        ] {
+            let scanner = Scanner::new(command.into());
+            assert!(
+                matches!(
+                    scanner.scan_tokens(),
+                    Err(ScanError::UnterminatedQuote(chr, idx)) if chr == quote && idx == position
+                ),
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn scan_comments() {
+        let command = "#!/bin/crsh\necho a#b # comment\narr=(x # first\ny) ${#x}";
+        let expected = [
+            Token::Newline,
+            reg_token!("echo"),
+            reg_token!("a#b"),
+            Token::Newline,
+            Token::Assignment("arr".into()),
+            Token::SubshellStart,
+            reg_token!("x"),
+            reg_token!("y"),
+            Token::SubshellEnd,
+            Token::Expansion(vec![WordPart::Parameter(
+                "x".into(),
+                ParameterOperator::Length,
+            )]),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+    }
+
+    #[test]
+    fn scan_line_continuation() {
+        let command = "echo one \\\n  tw\\\no \"th\\\nree\" 'a\\\nb'";
+        let expected = [
+            reg_token!("echo"),
+            reg_token!("one"),
+            reg_token!("two"),
+            reg_token!("three"),
+            reg_token!("a\\\nb"),
+            Token::EOF,
+        ];
+        let scanner = Scanner::new(command.into());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(expected[..], tokens[..]);
+        // the next line is still missing
+        for command in ["echo a \\", "echo a\\\n"] {
+            let scanner = Scanner::new(command.into());
+            assert!(matches!(
+                scanner.scan_tokens(),
+                Err(ScanError::LineContinuation)
+            ));
+        }
+    }
 }
@@ -0,0 +1,36 @@
+mod utils;
+
+#[cfg(test)]
+mod test_variables {
+    use crate::crsh;
+    use crsh::variables::{Subscript, Variables};
+
+    #[test]
+    fn largest_index() {
+        let mut variables = Variables::default();
+        let last = Subscript::Index(i64::MAX);
+        assert!(variables.set_element("a", last, "x".into()));
+        assert_eq!(Some("x"), variables.get_element("a", &Subscript::Index(-1)));
+        assert_eq!(None, variables.next_index("a"));
+        // counting back from the end reaches the first index, but not before it
+        assert!(variables.set_element("a", Subscript::Index(i64::MIN), "y".into()));
+        assert_eq!(Some("y"), variables.get_element("a", &Subscript::Index(0)));
+        assert!(variables.set_element("b", Subscript::Index(0), "z".into()));
+        assert!(!variables.set_element("b", Subscript::Index(i64::MIN), "z".into()));
+    }
+
+    #[test]
+    fn bad_subscript_status() {
+        // the error is printed by the assignment, the command only fails
+        for command in [
+            "a[-1]=x",
+            "a=([9223372036854775807]=x y)",
+            "a[9223372036854775807]=x; a+=(y)",
+        ] {
+            let output = crsh!("-c", command);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.ends_with("bad array subscript\n"), "{}", stderr);
+            assert_eq!(Some(1), output.status.code());
+        }
+    }
+}
@@ -5,9 +5,36 @@ mod utils {
         ($($x:expr),*) => (vec![$($x.to_string()),*])
     }
     #[macro_export]
+    macro_rules! word_vec {
+        ($($x:expr),*) => (vec![$(Node::Word($x.to_string())),*])
+    }
+    #[macro_export]
     macro_rules! reg_token {
         ($x:expr) => {
             Token::Regular($x.into())
         };
     }
+    // runs the built shell with the arguments, as in crsh!("-c", "echo"), optionally in
+    // another directory and with input piped into it, as in crsh!(; stdin = "echo")
+    #[macro_export]
+    macro_rules! crsh {
+        ($($arg:expr),* $(; current_dir = $dir:expr)? $(; stdin = $input:expr)?) => {{
+            use std::io::Write;
+            use std::process::{Command, Stdio};
+            let args: &[&str] = &[$($arg),*];
+            let input: &[&str] = &[$($input)?];
+            let mut command = Command::new(env!("CARGO_BIN_EXE_crsh"));
+            command
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            $(command.current_dir($dir);)?
+            let mut child = command.spawn().expect("failed to run crsh");
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(input.concat().as_bytes()).unwrap();
+            drop(stdin);
+            child.wait_with_output().unwrap()
+        }};
+    }
 }